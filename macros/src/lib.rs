@@ -0,0 +1,219 @@
+//! Attribute macros declaring `alloc-test` benchmarks.
+//!
+//! ```ignore
+//! use alloc_test::{alloc_test, perf_test};
+//!
+//! #[alloc_test(peak = "10%", total_num = 5)]
+//! fn parse_config() -> Config {
+//!     Config::parse(INPUT)
+//! }
+//!
+//! #[perf_test(mean_ns = "20%")]
+//! fn sort_large() -> Vec<u32> {
+//!     sorted(LARGE)
+//! }
+//! ```
+//!
+//! Benchmarks are identified by the path of the function, e.g.
+//! `my_crate::config::parse_config`, which also names their baseline files.
+//!
+//! Each threshold is either an integer, allowing that much absolute increase
+//! over the baseline, or a percentage string, allowing that much relative
+//! increase.
+//!
+//! Allocations of all threads are traced, so each generated test measures in
+//! a child process running only that test, on a single thread, see
+//! `alloc_test::harness::run_test`. The baseline options are read from the
+//! environment instead of the command line, see
+//! `alloc_test::threshold::check_threshold_in_test`. The benchmarks are also
+//! registered with `alloc_test::harness`, for `harness = false` targets, where
+//! `#[test]` functions are compiled out.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{
+    parse::Parser, parse_macro_input, punctuated::Punctuated, Error, Expr, ExprLit, ItemFn, Lit,
+    MetaNameValue, Token,
+};
+
+/// Declares a test checking allocations of the function against its baseline,
+/// and registers it as a benchmark for `alloc_test::harness`.
+#[proc_macro_attribute]
+pub fn alloc_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    expand(
+        attr.into(),
+        item,
+        quote!(::alloc_test::alloc::compare::AllocThresholdsBuilder),
+        quote!(::alloc_test::alloc::benchmark::alloc_benchmark),
+        "alloc_bench",
+    )
+    .unwrap_or_else(Error::into_compile_error)
+    .into()
+}
+
+/// Declares a test checking performance of the function against its baseline,
+/// and registers it as a benchmark for `alloc_test::harness`.
+#[proc_macro_attribute]
+pub fn perf_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    expand(
+        attr.into(),
+        item,
+        quote!(::alloc_test::perf::compare::PerfThresholdsBuilder),
+        quote!(::alloc_test::perf::benchmark::perf_benchmark),
+        "perf_bench",
+    )
+    .unwrap_or_else(Error::into_compile_error)
+    .into()
+}
+
+fn expand(
+    attr: TokenStream2,
+    item: ItemFn,
+    builder: TokenStream2,
+    benchmark: TokenStream2,
+    dir: &str,
+) -> syn::Result<TokenStream2> {
+    let params = Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse2(attr)?;
+    if !item.sig.inputs.is_empty() {
+        return Err(Error::new_spanned(
+            &item.sig.inputs,
+            "benchmarked functions cannot take arguments",
+        ));
+    }
+
+    let setters = params
+        .iter()
+        .map(|param| {
+            let name = param.path.get_ident().ok_or_else(|| {
+                Error::new_spanned(&param.path, "expected a statistics parameter name")
+            })?;
+            let threshold = threshold(&param.value)?;
+            Ok(quote_spanned!(name.span()=> .#name(#threshold)))
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let ItemFn {
+        attrs, vis, sig, ..
+    } = &item;
+    let name = &sig.ident;
+    let inner = ItemFn {
+        attrs: Vec::new(),
+        vis: syn::Visibility::Inherited,
+        ..item.clone()
+    };
+    // functions of the same name in different modules are distinct benchmarks
+    let id = quote!(concat!(module_path!(), "::", stringify!(#name)));
+    let body = |check: TokenStream2| {
+        quote! {
+            #inner
+            let thresholds = #builder::default()
+                #(#setters)*
+                .build()
+                .unwrap();
+            ::alloc_test::threshold::#check(
+                || #benchmark(#id, #name),
+                #dir,
+                #id,
+                &thresholds,
+            )
+            .unwrap();
+        }
+    };
+    let test_body = body(quote!(check_threshold_in_test));
+    let bench_body = body(quote!(check_threshold_with_args));
+    Ok(quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #name() {
+            fn run() {
+                #test_body
+            }
+            ::alloc_test::harness::run_test(#id, run)
+        }
+
+        ::alloc_test::harness::inventory::submit! {
+            ::alloc_test::harness::Benchmark {
+                name: #id,
+                run: {
+                    fn run() {
+                        #bench_body
+                    }
+                    run
+                },
+            }
+        }
+    })
+}
+
+/// Parsed threshold value.
+#[derive(Debug, PartialEq, Eq)]
+enum Threshold {
+    Cap(u64),
+    Ratio(u64, u64),
+}
+
+fn threshold(value: &Expr) -> syn::Result<TokenStream2> {
+    let invalid = || Error::new_spanned(value, "expected an integer or a percentage like \"10%\"");
+    let threshold = match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(int), ..
+        }) => Threshold::Cap(int.base10_parse()?),
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(s), ..
+        }) => parse_threshold(&s.value()).ok_or_else(invalid)?,
+        _ => return Err(invalid()),
+    };
+    let span = Span::call_site();
+    Ok(match threshold {
+        Threshold::Cap(cap) => {
+            let cap = syn::LitInt::new(&cap.to_string(), span);
+            quote!(::alloc_test::threshold::Threshold::cap(#cap))
+        }
+        Threshold::Ratio(numer, denom) => {
+            let numer = syn::LitInt::new(&numer.to_string(), span);
+            let denom = syn::LitInt::new(&denom.to_string(), span);
+            quote!(::alloc_test::threshold::Threshold::ratio(#numer, #denom))
+        }
+    })
+}
+
+/// Parses `"10%"`, `"2.5%"` or `"100"`.
+fn parse_threshold(s: &str) -> Option<Threshold> {
+    let s = s.trim();
+    let Some(percent) = s.strip_suffix('%') else {
+        return s.parse().ok().map(Threshold::Cap);
+    };
+    let (int, frac) = percent
+        .trim()
+        .split_once('.')
+        .unwrap_or((percent.trim(), ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(int) || !digits(frac) {
+        return None;
+    }
+    let numer = format!("{int}{frac}").parse().ok()?;
+    let denom = 10_u64.checked_pow(frac.len() as u32)?.checked_mul(100)?;
+    Some(Threshold::Ratio(numer, denom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(parse_threshold("100"), Some(Threshold::Cap(100)));
+        assert_eq!(parse_threshold("10%"), Some(Threshold::Ratio(10, 100)));
+        assert_eq!(parse_threshold("2.5%"), Some(Threshold::Ratio(25, 1000)));
+        assert_eq!(parse_threshold(".5%"), Some(Threshold::Ratio(5, 1000)));
+        assert_eq!(parse_threshold("%"), None);
+        assert_eq!(parse_threshold("-1%"), None);
+        assert_eq!(parse_threshold("ten"), None);
+    }
+}
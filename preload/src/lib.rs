@@ -0,0 +1,276 @@
+//! `LD_PRELOAD` shim forwarding C heap operations to `alloc-test`.
+//!
+//! The shim overrides `malloc`, `calloc`, `realloc`, `free`, `posix_memalign`,
+//! `aligned_alloc` and `memalign`, forwards them to the next definition in the
+//! lookup order (normally glibc) and reports each event to the hooks
+//! registered by `alloc_test::alloc::preload::register`.
+//!
+//! ```sh
+//! cargo build -p alloc-test-preload --release
+//! LD_PRELOAD=target/release/liballoc_test_preload.so cargo test
+//! ```
+//!
+//! Sizes reported to the hooks are usable sizes, as returned by
+//! `malloc_usable_size`, since `free` is not given the size of the block.
+//!
+//! Nothing here may allocate through the Rust global allocator, which itself
+//! calls `malloc`.
+
+// the overrides have the same contracts as their libc counterparts
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    ffi::c_void,
+    mem, ptr,
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+};
+
+use libc::{c_int, size_t};
+
+/// Callbacks registered by the traced process.
+///
+/// Must match `alloc_test::alloc::preload::PreloadHooks`.
+#[repr(C)]
+pub struct PreloadHooks {
+    pub ctx: *const c_void,
+    pub on_alloc: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    pub on_dealloc: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    pub on_alloc_zeroed: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    pub on_realloc: unsafe extern "C" fn(*const c_void, *mut u8, *mut u8, usize, usize, usize),
+}
+
+static HOOKS: AtomicPtr<PreloadHooks> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers `hooks` to be called on every C heap operation. Passing null
+/// unregisters them. `hooks` must stay valid for as long as it is registered.
+///
+/// # Safety
+///
+/// `hooks` must be null or point to a valid `PreloadHooks`.
+#[no_mangle]
+pub unsafe extern "C" fn alloc_test_preload_register(hooks: *const PreloadHooks) {
+    HOOKS.store(hooks as *mut _, Ordering::Release);
+}
+
+fn hooks() -> Option<&'static PreloadHooks> {
+    unsafe { HOOKS.load(Ordering::Acquire).as_ref() }
+}
+
+type MallocFn = unsafe extern "C" fn(size_t) -> *mut c_void;
+type CallocFn = unsafe extern "C" fn(size_t, size_t) -> *mut c_void;
+type ReallocFn = unsafe extern "C" fn(*mut c_void, size_t) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type PosixMemalignFn = unsafe extern "C" fn(*mut *mut c_void, size_t, size_t) -> c_int;
+type AlignedAllocFn = unsafe extern "C" fn(size_t, size_t) -> *mut c_void;
+
+struct Real {
+    malloc: MallocFn,
+    calloc: CallocFn,
+    realloc: ReallocFn,
+    free: FreeFn,
+    posix_memalign: PosixMemalignFn,
+    aligned_alloc: AlignedAllocFn,
+    memalign: AlignedAllocFn,
+}
+
+const UNRESOLVED: u8 = 0;
+const RESOLVING: u8 = 1;
+const RESOLVED: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNRESOLVED);
+static mut REAL: mem::MaybeUninit<Real> = mem::MaybeUninit::uninit();
+
+/// Real allocator functions, or `None` while they are being resolved.
+fn real() -> Option<&'static Real> {
+    match STATE.compare_exchange(UNRESOLVED, RESOLVING, Ordering::Acquire, Ordering::Acquire) {
+        Ok(_) => unsafe {
+            let real = Real {
+                malloc: mem::transmute::<*mut c_void, MallocFn>(next(c"malloc")),
+                calloc: mem::transmute::<*mut c_void, CallocFn>(next(c"calloc")),
+                realloc: mem::transmute::<*mut c_void, ReallocFn>(next(c"realloc")),
+                free: mem::transmute::<*mut c_void, FreeFn>(next(c"free")),
+                posix_memalign: mem::transmute::<*mut c_void, PosixMemalignFn>(next(
+                    c"posix_memalign",
+                )),
+                aligned_alloc: mem::transmute::<*mut c_void, AlignedAllocFn>(next(
+                    c"aligned_alloc",
+                )),
+                memalign: mem::transmute::<*mut c_void, AlignedAllocFn>(next(c"memalign")),
+            };
+            (*ptr::addr_of_mut!(REAL)).write(real);
+            STATE.store(RESOLVED, Ordering::Release);
+            Some((*ptr::addr_of!(REAL)).assume_init_ref())
+        },
+        Err(RESOLVED) => unsafe { Some((*ptr::addr_of!(REAL)).assume_init_ref()) },
+        Err(_) => None,
+    }
+}
+
+unsafe fn next(name: &std::ffi::CStr) -> *mut c_void {
+    let f = libc::dlsym(libc::RTLD_NEXT, name.as_ptr());
+    if f.is_null() {
+        libc::abort();
+    }
+    f
+}
+
+/// `dlsym` may allocate, so allocations made while resolving the real
+/// functions are served from a static buffer and never freed. Each block is
+/// preceded by a header holding its size.
+const BOOTSTRAP_SIZE: usize = 64 * 1024;
+const HEADER: usize = 16;
+
+#[repr(C, align(16))]
+struct Bootstrap([u8; BOOTSTRAP_SIZE]);
+
+static mut BOOTSTRAP: Bootstrap = Bootstrap([0; BOOTSTRAP_SIZE]);
+static BOOTSTRAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe fn bootstrap_alloc(size: usize) -> *mut c_void {
+    let block = (HEADER + size).next_multiple_of(HEADER);
+    let offset = BOOTSTRAP_USED.fetch_add(block, Ordering::Relaxed);
+    if offset + block > BOOTSTRAP_SIZE {
+        return ptr::null_mut();
+    }
+    let base = ptr::addr_of_mut!(BOOTSTRAP).cast::<u8>().add(offset);
+    base.cast::<usize>().write(size);
+    base.add(HEADER).cast()
+}
+
+fn is_bootstrap(pointer: *mut c_void) -> bool {
+    let base = ptr::addr_of!(BOOTSTRAP) as usize;
+    (base..base + BOOTSTRAP_SIZE).contains(&(pointer as usize))
+}
+
+unsafe fn bootstrap_size(pointer: *mut c_void) -> usize {
+    pointer.cast::<u8>().sub(HEADER).cast::<usize>().read()
+}
+
+unsafe fn usable_size(pointer: *mut c_void) -> usize {
+    libc::malloc_usable_size(pointer)
+}
+
+unsafe fn report_alloc(pointer: *mut c_void, align: usize) {
+    if let Some(hooks) = hooks().filter(|_| !pointer.is_null()) {
+        (hooks.on_alloc)(hooks.ctx, pointer.cast(), usable_size(pointer), align);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
+    let Some(real) = real() else {
+        return bootstrap_alloc(size);
+    };
+    let pointer = (real.malloc)(size);
+    report_alloc(pointer, 1);
+    pointer
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn calloc(num: size_t, size: size_t) -> *mut c_void {
+    let Some(real) = real() else {
+        // the static buffer is zero-initialized and never reused
+        return match num.checked_mul(size) {
+            Some(size) => bootstrap_alloc(size),
+            None => ptr::null_mut(),
+        };
+    };
+    let pointer = (real.calloc)(num, size);
+    if let Some(hooks) = hooks().filter(|_| !pointer.is_null()) {
+        (hooks.on_alloc_zeroed)(hooks.ctx, pointer.cast(), usable_size(pointer), 1);
+    }
+    pointer
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn realloc(pointer: *mut c_void, size: size_t) -> *mut c_void {
+    let Some(real) = real() else {
+        let new_pointer = bootstrap_alloc(size);
+        if !pointer.is_null() && !new_pointer.is_null() {
+            let old_size = bootstrap_size(pointer).min(size);
+            ptr::copy_nonoverlapping(pointer.cast::<u8>(), new_pointer.cast(), old_size);
+        }
+        return new_pointer;
+    };
+    if is_bootstrap(pointer) {
+        let new_pointer = malloc(size);
+        if !new_pointer.is_null() {
+            let old_size = bootstrap_size(pointer).min(size);
+            ptr::copy_nonoverlapping(pointer.cast::<u8>(), new_pointer.cast(), old_size);
+        }
+        return new_pointer;
+    }
+    if pointer.is_null() {
+        return malloc(size);
+    }
+
+    let old_size = usable_size(pointer);
+    let new_pointer = (real.realloc)(pointer, size);
+    if let Some(hooks) = hooks() {
+        if !new_pointer.is_null() {
+            let new_size = usable_size(new_pointer);
+            (hooks.on_realloc)(
+                hooks.ctx,
+                pointer.cast(),
+                new_pointer.cast(),
+                old_size,
+                new_size,
+                1,
+            );
+        } else if size == 0 {
+            // glibc frees the block on `realloc(p, 0)`
+            (hooks.on_dealloc)(hooks.ctx, pointer.cast(), old_size, 1);
+        }
+    }
+    new_pointer
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free(pointer: *mut c_void) {
+    if pointer.is_null() || is_bootstrap(pointer) {
+        return;
+    }
+    let Some(real) = real() else {
+        return;
+    };
+    if let Some(hooks) = hooks() {
+        (hooks.on_dealloc)(hooks.ctx, pointer.cast(), usable_size(pointer), 1);
+    }
+    (real.free)(pointer);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(
+    pointer: *mut *mut c_void,
+    align: size_t,
+    size: size_t,
+) -> c_int {
+    let Some(real) = real() else {
+        return libc::ENOMEM;
+    };
+    let result = (real.posix_memalign)(pointer, align, size);
+    if result == 0 {
+        report_alloc(*pointer, align);
+    }
+    result
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn aligned_alloc(align: size_t, size: size_t) -> *mut c_void {
+    let Some(real) = real() else {
+        return ptr::null_mut();
+    };
+    let pointer = (real.aligned_alloc)(align, size);
+    report_alloc(pointer, align);
+    pointer
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memalign(align: size_t, size: size_t) -> *mut c_void {
+    let Some(real) = real() else {
+        return ptr::null_mut();
+    };
+    let pointer = (real.memalign)(align, size);
+    report_alloc(pointer, align);
+    pointer
+}
@@ -1,4 +1,7 @@
-use std::alloc::{GlobalAlloc, Layout};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+};
 
 #[derive(Debug, Default)]
 pub struct TracingAllocator<H: 'static, A>(A, H)
@@ -18,6 +21,23 @@ where
 //     TracingAllocator(System, MemoryTracingHooks)
 // }
 
+thread_local! {
+    static IN_TRACING_ALLOCATOR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread is inside a [`TracingAllocator`] call, e.g. when
+/// the inner allocator calls into `malloc`.
+pub(crate) fn in_tracing_allocator() -> bool {
+    IN_TRACING_ALLOCATOR.with(Cell::get)
+}
+
+fn inner<F: FnOnce() -> T, T>(f: F) -> T {
+    let outer = IN_TRACING_ALLOCATOR.with(|c| c.replace(true));
+    let t = f();
+    IN_TRACING_ALLOCATOR.with(|c| c.set(outer));
+    t
+}
+
 unsafe impl<H, A> GlobalAlloc for TracingAllocator<H, A>
 where
     A: GlobalAlloc,
@@ -26,7 +46,7 @@ where
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
         let align = layout.align();
-        let pointer = self.0.alloc(layout);
+        let pointer = inner(|| self.0.alloc(layout));
         self.1.on_alloc(pointer, size, align);
         pointer
     }
@@ -34,14 +54,14 @@ where
     unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
         let size = layout.size();
         let align = layout.align();
-        self.0.dealloc(pointer, layout);
+        inner(|| self.0.dealloc(pointer, layout));
         self.1.on_dealloc(pointer, size, align);
     }
 
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
         let align = layout.align();
-        let pointer = self.0.alloc_zeroed(layout);
+        let pointer = inner(|| self.0.alloc_zeroed(layout));
         self.1.on_alloc_zeroed(pointer, size, align);
         pointer
     }
@@ -49,13 +69,20 @@ where
     unsafe fn realloc(&self, old_pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         let old_size = layout.size();
         let align = layout.align();
-        let new_pointer = self.0.realloc(old_pointer, layout, new_size);
+        let new_pointer = inner(|| self.0.realloc(old_pointer, layout, new_size));
         self.1
             .on_realloc(old_pointer, new_pointer, old_size, new_size, align);
         new_pointer
     }
 }
 
+/// Callbacks invoked by [`TracingAllocator`] after each allocator operation.
+///
+/// # Safety
+///
+/// The hooks are called from inside the global allocator, so implementations
+/// must not unwind and must be careful not to recurse infinitely when they
+/// allocate themselves.
 pub unsafe trait AllocHooks {
     fn on_alloc(&self, pointer: *mut u8, size: usize, align: usize);
     fn on_dealloc(&self, pointer: *mut u8, size: usize, align: usize);
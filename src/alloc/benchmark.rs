@@ -1,4 +1,9 @@
-use super::measure::MemoryStats;
+use derive_builder::Builder;
+
+use super::{
+    determinism::StatsRange,
+    measure::{MemoryStats, TraceOptions, TraceReport},
+};
 
 pub fn alloc_benchmark<F: FnOnce() -> O, O>(id: &str, f: F) -> MemoryStats {
     let (_, stats) = crate::alloc::measure::trace_allocs(f);
@@ -6,6 +11,138 @@ pub fn alloc_benchmark<F: FnOnce() -> O, O>(id: &str, f: F) -> MemoryStats {
     stats
 }
 
+/// Like [`alloc_benchmark`], but the input of `f` is built by `setup` and its
+/// output is consumed by `teardown`, neither of which is traced. `f` may
+/// consume its input, freeing it doesn't count against the traced stats.
+pub fn alloc_benchmark_with_setup<I, O, S, F, T>(
+    id: &str,
+    setup: S,
+    f: F,
+    teardown: T,
+) -> MemoryStats
+where
+    S: FnOnce() -> I,
+    F: FnOnce(I) -> O,
+    T: FnOnce(O),
+{
+    let (output, stats) = crate::alloc::measure::trace_allocs_with_setup(setup, f);
+    teardown(output);
+    log!("\nmemory allocation stats for `{id}`:\n{stats}");
+    stats
+}
+
+/// Options of [`alloc_benchmark_with`].
+#[derive(Debug, Clone, Builder)]
+pub struct AllocBenchOptions {
+    /// What to record while tracing.
+    #[builder(default)]
+    pub trace: TraceOptions,
+    /// Number of runs, each traced separately. When greater than one, the
+    /// stats of all runs are expected to be identical and a warning listing
+    /// the varying fields is logged otherwise.
+    #[builder(default = "1")]
+    pub runs: usize,
+    /// Number of runs made before the measured ones, letting lazily
+    /// initialized statics and thread-locals settle. The first of them is
+    /// traced separately, see [`AllocBenchReport::first_run`].
+    #[builder(default)]
+    pub warmup: usize,
+}
+
+impl Default for AllocBenchOptions {
+    fn default() -> Self {
+        AllocBenchOptionsBuilder::default().build().unwrap()
+    }
+}
+
+/// Stats of a benchmark run by [`alloc_benchmark_report`].
+#[derive(Debug, Clone)]
+pub struct AllocBenchReport {
+    /// Stats of the first warm-up run, including one-time initialization.
+    /// `None` without warm-up.
+    pub first_run: Option<MemoryStats>,
+    /// Stats of the measured runs.
+    pub stats: MemoryStats,
+}
+
+/// Like [`alloc_benchmark`], configured by `options`.
+///
+/// Logs whatever `options.trace` asks
+/// [`trace_allocs_with`](super::measure::trace_allocs_with) to record for the
+/// first measured run. If several runs are measured, returns the field-wise
+/// maximum of their stats.
+pub fn alloc_benchmark_with<F: Fn() -> O, O>(
+    id: &str,
+    options: &AllocBenchOptions,
+    f: F,
+) -> MemoryStats {
+    alloc_benchmark_report(id, options, f).stats
+}
+
+/// Like [`alloc_benchmark_with`], also returning the first-run stats.
+pub fn alloc_benchmark_report<F: Fn() -> O, O>(
+    id: &str,
+    options: &AllocBenchOptions,
+    f: F,
+) -> AllocBenchReport {
+    let first_run = (options.warmup > 0).then(|| {
+        let (_, first_run) = super::measure::trace_allocs(&f);
+        log!("\nfirst-run memory allocation stats for `{id}`:\n{first_run}");
+        for _ in 1..options.warmup {
+            f();
+        }
+        first_run
+    });
+
+    let (_, report) = super::measure::trace_allocs_with(&options.trace, &f);
+    log!("\nmemory allocation stats for `{id}`:\n{}", report.stats);
+    log_report(id, &report);
+    if options.runs <= 1 {
+        return AllocBenchReport {
+            first_run,
+            stats: report.stats,
+        };
+    }
+
+    let mut runs = vec![report.stats];
+    runs.extend((1..options.runs).map(|_| super::measure::trace_allocs(&f).1));
+    let range = StatsRange::of(&runs).unwrap_or_default();
+    if !range.is_deterministic() {
+        log!(
+            "warning: allocation stats for `{id}` vary across {} runs:\n{range}",
+            options.runs
+        );
+    }
+    AllocBenchReport {
+        first_run,
+        stats: range.max,
+    }
+}
+
+fn log_report(id: &str, report: &TraceReport) {
+    let stats = &report.stats;
+    if let Some(rss) = &report.rss {
+        log!(
+            "{rss}Untraced RSS growth (B): {}\nUntraced peak RSS growth (B): {}",
+            rss.untraced_growth(stats),
+            rss.untraced_peak_growth(stats)
+        );
+        if rss.has_gap(stats) {
+            log!("warning: RSS grew by much more than the traced heap for `{id}`; memory may be allocated by mmap, C code or lost to fragmentation");
+        }
+    }
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    if let Some(mallinfo) = &report.mallinfo {
+        log!("{mallinfo}");
+        if let Some(ratio) = mallinfo.fragmentation_ratio(stats) {
+            log!("Estimated fragmentation ratio: {ratio:.2}");
+        }
+    }
+    if let Some(snapshot) = &report.peak_snapshot {
+        log!("{snapshot}");
+    }
+}
+
 pub fn alloc_log_toml<F: Fn() -> O, O>(id: &str, f: F) -> MemoryStats {
     let (_, stats) = super::measure::trace_allocs(f);
     log!(
@@ -15,30 +152,102 @@ pub fn alloc_log_toml<F: Fn() -> O, O>(id: &str, f: F) -> MemoryStats {
     stats
 }
 
+/// Checks allocations of a benchmark against its baseline.
+///
+/// `alloc_bench!(test, thresholds)` benchmarks the function `test`, using its
+/// name as the baseline id. `alloc_bench!(id, f, thresholds)` benchmarks any
+/// callable expression `f` (a closure, a path to a function) under the
+/// explicit id, so that several cases can be benchmarked by one test:
+///
+/// ```ignore
+/// for n in [10, 100, 1000] {
+///     alloc_bench!(format!("sort_{n}"), || sort(n), &thresholds).unwrap();
+/// }
+/// ```
 #[macro_export]
 macro_rules! alloc_bench {
     ($test:ident, $thresh:expr) => {
+        $crate::alloc_bench!(stringify!($test), $test, $thresh)
+    };
+    ($id:expr, $f:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let f = $f;
         $crate::threshold::check_threshold_with_args(
-            || $crate::alloc::benchmark::alloc_benchmark(stringify!($test), $test),
+            || $crate::alloc::benchmark::alloc_benchmark(id, &f),
             "alloc_bench",
-            stringify!($test),
+            id,
             $thresh,
         )
+    }};
+}
+
+/// Like [`alloc_bench!`], with the benchmark configured by
+/// [`AllocBenchOptions`].
+///
+/// Accepts either `(test, options, thresholds)` or
+/// `(id, f, options, thresholds)`.
+#[macro_export]
+macro_rules! alloc_bench_with {
+    ($test:ident, $options:expr, $thresh:expr) => {
+        $crate::alloc_bench_with!(stringify!($test), $test, $options, $thresh)
+    };
+    ($id:expr, $f:expr, $options:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let (f, options) = ($f, $options);
+        $crate::threshold::check_threshold_with_args(
+            || $crate::alloc::benchmark::alloc_benchmark_with(id, &options, &f),
+            "alloc_bench",
+            id,
+            $thresh,
+        )
+    }};
+}
+
+/// Like [`alloc_bench!`], but the input of the benchmarked function is built
+/// by `setup` and its output is consumed by `teardown`, outside of the traced
+/// region.
+///
+/// Accepts either `(test, setup, teardown, thresholds)` or
+/// `(id, f, setup, teardown, thresholds)`.
+#[macro_export]
+macro_rules! alloc_bench_with_setup {
+    ($test:ident, $setup:expr, $teardown:expr, $thresh:expr) => {
+        $crate::alloc_bench_with_setup!(stringify!($test), $test, $setup, $teardown, $thresh)
     };
+    ($id:expr, $f:expr, $setup:expr, $teardown:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let (f, setup, teardown) = ($f, $setup, $teardown);
+        $crate::threshold::check_threshold_with_args(
+            || $crate::alloc::benchmark::alloc_benchmark_with_setup(id, &setup, &f, &teardown),
+            "alloc_bench",
+            id,
+            $thresh,
+        )
+    }};
 }
 
+/// Like [`alloc_bench!`], but compares with a baseline given as a TOML
+/// string. Without a baseline, only logs the stats in TOML format.
 #[macro_export]
 macro_rules! alloc_bench_cmp_with_toml {
-    ($test:ident $(,)?) => {{
-        let value = $crate::alloc::benchmark::alloc_log_toml(stringify!($test), $test);
+    ($test:ident $(,)?) => {
+        $crate::alloc_bench_cmp_with_toml!(stringify!($test), $test)
+    };
+    ($test:ident, $toml:expr, $limits:expr $(,)?) => {
+        $crate::alloc_bench_cmp_with_toml!(stringify!($test), $test, $toml, $limits)
+    };
+    ($id:expr, $f:expr $(,)?) => {{
+        let value = $crate::alloc::benchmark::alloc_log_toml(&$id, $f);
         Result::<
             $crate::alloc::measure::MemoryStats,
             $crate::threshold::CheckThresholdError<$crate::alloc::compare::AllocThresholdsError>,
         >::Ok(value)
     }};
-    ($test:ident, $toml:expr, $limits:expr $(,)?) => {{
+    ($id:expr, $f:expr, $toml:expr, $limits:expr $(,)?) => {{
+        let id: &str = &$id;
+        let f = $f;
         $crate::threshold::check_threshold_with_str(
-            || $crate::alloc::benchmark::alloc_benchmark(stringify!($test), $test),
+            || $crate::alloc::benchmark::alloc_benchmark(id, &f),
             $toml,
             $limits,
         )
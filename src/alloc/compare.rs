@@ -5,7 +5,7 @@ use super::measure::MemoryStats;
 use crate::threshold::{Threshold, ThresholdError, ThresholdFor};
 
 /// Limits for each allocation statistics parameter.
-#[derive(Debug, Builder)]
+#[derive(Debug, Clone, Builder)]
 pub struct AllocThresholds {
     #[builder(default)]
     pub current: Threshold<usize>,
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use super::measure::{trace_allocs, MemoryStats};
+
+/// Field-wise minimum and maximum of [`MemoryStats`] over several runs.
+#[derive(Debug, Clone, Default)]
+pub struct StatsRange {
+    pub min: MemoryStats,
+    pub max: MemoryStats,
+}
+
+macro_rules! for_each_field {
+    ($m:ident) => {
+        $m!(current);
+        $m!(peak);
+        $m!(total_size);
+        $m!(total_num);
+        $m!(reallocs);
+    };
+}
+
+impl StatsRange {
+    /// Range of `runs`, or `None` if there are none.
+    pub fn of(runs: &[MemoryStats]) -> Option<Self> {
+        let (first, rest) = runs.split_first()?;
+        let mut range = StatsRange {
+            min: first.clone(),
+            max: first.clone(),
+        };
+        for stats in rest {
+            macro_rules! update {
+                ($f:ident) => {
+                    range.min.$f = range.min.$f.min(stats.$f);
+                    range.max.$f = range.max.$f.max(stats.$f);
+                };
+            }
+            for_each_field!(update);
+        }
+        Some(range)
+    }
+
+    /// Names of the fields that differ between runs.
+    pub fn varying(&self) -> Vec<&'static str> {
+        let mut varying = Vec::new();
+        macro_rules! check {
+            ($f:ident) => {
+                if self.min.$f != self.max.$f {
+                    varying.push(stringify!($f));
+                }
+            };
+        }
+        for_each_field!(check);
+        varying
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.varying().is_empty()
+    }
+}
+
+impl fmt::Display for StatsRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        macro_rules! write_varying {
+            ($f:ident) => {
+                if self.min.$f != self.max.$f {
+                    writeln!(f, "{}: {}..={}", stringify!($f), self.min.$f, self.max.$f)?;
+                }
+            };
+        }
+        for_each_field!(write_varying);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("allocation stats vary across {runs} runs:\n{range}")]
+pub struct NondeterminismError {
+    pub runs: usize,
+    pub range: StatsRange,
+}
+
+/// Runs `f` `runs` times, each in its own [`trace_allocs`] scope, and checks
+/// that the recorded stats are the same every time.
+pub fn check_determinism<F: Fn() -> O, O>(
+    runs: usize,
+    f: F,
+) -> Result<MemoryStats, NondeterminismError> {
+    let stats = (0..runs.max(1))
+        .map(|_| trace_allocs(&f).1)
+        .collect::<Vec<_>>();
+    let range = StatsRange::of(&stats).unwrap_or_default();
+    if range.is_deterministic() {
+        Ok(range.max)
+    } else {
+        Err(NondeterminismError { runs, range })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_range() {
+        let stats = |peak, total_num| MemoryStats {
+            peak,
+            total_num,
+            ..Default::default()
+        };
+        let range = StatsRange::of(&[stats(10, 1), stats(10, 1)]).unwrap();
+        assert!(range.is_deterministic());
+
+        let range = StatsRange::of(&[stats(10, 1), stats(30, 1), stats(20, 1)]).unwrap();
+        assert_eq!(range.varying(), ["peak"]);
+        assert_eq!((range.min.peak, range.max.peak), (10, 30));
+        assert_eq!(range.to_string(), "peak: 10..=30\n");
+
+        assert!(StatsRange::of(&[]).is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use derive_builder::Builder;
+use thiserror::Error;
+
+use super::measure::{current_stats, trace_allocs};
+
+/// Parameters of the leak-by-growth detector.
+#[derive(Debug, Clone, Builder)]
+pub struct GrowthOptions {
+    /// Number of measured iterations.
+    #[builder(default = "100")]
+    pub iterations: usize,
+    /// Number of initial iterations excluded from the trend, letting caches
+    /// and other lazily grown structures settle.
+    #[builder(default = "10")]
+    pub warmup: usize,
+    /// Allowed growth of retained memory, in bytes per iteration.
+    #[builder(default = "0.0")]
+    pub tolerance: f64,
+}
+
+impl Default for GrowthOptions {
+    fn default() -> Self {
+        GrowthOptionsBuilder::default().build().unwrap()
+    }
+}
+
+/// Retained memory observed after each iteration and the fitted trend.
+#[derive(Debug, Clone)]
+pub struct GrowthReport {
+    /// `MemoryStats::current` after each iteration, warm-up included.
+    pub samples: Vec<usize>,
+    /// Growth of retained memory, in bytes per iteration.
+    pub slope: f64,
+}
+
+#[derive(Debug, Error)]
+#[error("retained memory grows by {:.1} B per iteration (allowed {tolerance})", report.slope)]
+pub struct LeakError {
+    pub report: GrowthReport,
+    pub tolerance: f64,
+}
+
+/// Runs `f` repeatedly inside a single traced scope, dropping its output each
+/// time, and fails if the memory retained after an iteration keeps growing.
+///
+/// ```
+/// use std::alloc::System;
+/// use alloc_test::alloc::{
+///     allocator::TracingAllocator, default_tracing_allocator,
+///     leak::{check_growth, GrowthOptions}, measure::MemoryTracingHooks,
+/// };
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+///
+/// fn main() {
+///     assert!(check_growth(&GrowthOptions::default(), || vec![0_u8; 16]).is_ok());
+///     assert!(check_growth(&GrowthOptions::default(), || Box::leak(Box::new([0_u8; 16]))).is_err());
+/// }
+/// ```
+pub fn check_growth<F: FnMut() -> O, O>(
+    options: &GrowthOptions,
+    mut f: F,
+) -> Result<GrowthReport, LeakError> {
+    let iterations = options.warmup + options.iterations;
+    // allocated upfront so that recording samples is not traced
+    let mut samples = Vec::with_capacity(iterations);
+    let ((), _) = trace_allocs(|| {
+        for _ in 0..iterations {
+            drop(f());
+            samples.push(current_stats().current);
+        }
+    });
+
+    let slope = slope(&samples[options.warmup.min(samples.len())..]);
+    let report = GrowthReport { samples, slope };
+    if slope > options.tolerance {
+        Err(LeakError {
+            report,
+            tolerance: options.tolerance,
+        })
+    } else {
+        Ok(report)
+    }
+}
+
+/// Least-squares slope of `samples` against their indices.
+fn slope(samples: &[usize]) -> f64 {
+    let n = samples.len() as f64;
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = samples.iter().map(|&y| y as f64).sum::<f64>() / n;
+    let (cov, var) = samples
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(cov, var), (x, &y)| {
+            let dx = x as f64 - mean_x;
+            (cov + dx * (y as f64 - mean_y), var + dx * dx)
+        });
+    cov / var
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growth_slope() {
+        assert_eq!(slope(&[]), 0.0);
+        assert_eq!(slope(&[100]), 0.0);
+        assert_eq!(slope(&[100, 100, 100, 100]), 0.0);
+        assert_eq!(slope(&[100, 116, 132, 148]), 16.0);
+        assert!(slope(&[100, 164, 164, 164, 164]) < 20.0);
+    }
+}
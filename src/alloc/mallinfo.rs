@@ -0,0 +1,144 @@
+//! glibc allocator internals, as reported by `mallinfo2`.
+//!
+//! Only meaningful when the inner allocator of the
+//! [`TracingAllocator`](super::allocator::TracingAllocator) is
+//! [`System`](std::alloc::System). `mallinfo2` is looked up at runtime, so
+//! that glibc older than 2.33 only loses these reports.
+
+use std::{mem, sync::OnceLock};
+
+use derive_more::Display;
+
+use super::measure::MemoryStats;
+
+/// Snapshot of the glibc allocator state, in bytes unless noted otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mallinfo {
+    /// Non-mmapped memory obtained from the system.
+    pub arena: usize,
+    /// Number of free chunks.
+    pub free_chunks: usize,
+    /// Free bytes held by the allocator.
+    pub free: usize,
+    /// Memory in mmapped regions.
+    pub mmapped: usize,
+    /// Bytes in use by allocations, excluding mmapped ones.
+    pub in_use: usize,
+}
+
+impl Mallinfo {
+    /// Current allocator state, `None` if glibc doesn't provide `mallinfo2`.
+    pub fn now() -> Option<Self> {
+        let info = unsafe { mallinfo2()?() };
+        Some(Mallinfo {
+            arena: info.arena,
+            free_chunks: info.ordblks,
+            free: info.fordblks,
+            mmapped: info.hblkhd,
+            in_use: info.uordblks,
+        })
+    }
+
+    /// Total memory the allocator obtained from the system.
+    pub fn footprint(&self) -> usize {
+        self.arena + self.mmapped
+    }
+}
+
+type Mallinfo2 = unsafe extern "C" fn() -> libc::mallinfo2;
+
+fn mallinfo2() -> Option<Mallinfo2> {
+    static MALLINFO2: OnceLock<Option<Mallinfo2>> = OnceLock::new();
+    *MALLINFO2.get_or_init(|| {
+        let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, c"mallinfo2".as_ptr()) };
+        (!symbol.is_null())
+            .then(|| unsafe { mem::transmute::<*mut libc::c_void, Mallinfo2>(symbol) })
+    })
+}
+
+/// Allocator state before and after a traced closure.
+#[derive(Debug, Clone, Copy, Default, Display)]
+#[display(
+    fmt = r#"Arena size delta (B): {}
+Free chunks delta (N): {}
+Free bytes delta (B): {}
+Mmapped delta (B): {}
+"#,
+    "self.arena_delta()",
+    "self.free_chunks_delta()",
+    "self.free_delta()",
+    "self.mmapped_delta()"
+)]
+pub struct MallinfoReport {
+    pub before: Mallinfo,
+    pub after: Mallinfo,
+}
+
+fn delta(before: usize, after: usize) -> isize {
+    after as isize - before as isize
+}
+
+impl MallinfoReport {
+    pub fn arena_delta(&self) -> isize {
+        delta(self.before.arena, self.after.arena)
+    }
+
+    pub fn free_chunks_delta(&self) -> isize {
+        delta(self.before.free_chunks, self.after.free_chunks)
+    }
+
+    pub fn free_delta(&self) -> isize {
+        delta(self.before.free, self.after.free)
+    }
+
+    pub fn mmapped_delta(&self) -> isize {
+        delta(self.before.mmapped, self.after.mmapped)
+    }
+
+    /// Growth of the allocator footprint per byte of traced live memory.
+    ///
+    /// Values well above 1 mean that the allocator holds on to much more
+    /// memory than is actually allocated, i.e. a peak regression is likely
+    /// caused by allocator behaviour rather than by the traced code. `None`
+    /// if nothing remained allocated.
+    pub fn fragmentation_ratio(&self, stats: &MemoryStats) -> Option<f64> {
+        let growth = delta(self.before.footprint(), self.after.footprint());
+        (stats.current > 0).then(|| growth as f64 / stats.current as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn now() {
+        // `None` on glibc older than 2.33
+        if let Some(info) = Mallinfo::now() {
+            let v = vec![0_u8; 1000];
+            assert!(Mallinfo::now().unwrap().in_use >= info.in_use + v.len());
+        }
+    }
+
+    #[test]
+    fn fragmentation_ratio() {
+        let report = MallinfoReport {
+            before: Mallinfo {
+                arena: 1000,
+                ..Default::default()
+            },
+            after: Mallinfo {
+                arena: 3000,
+                mmapped: 1000,
+                ..Default::default()
+            },
+        };
+        let stats = |current| MemoryStats {
+            current,
+            ..Default::default()
+        };
+        assert_eq!(report.fragmentation_ratio(&stats(0)), None);
+        assert_eq!(report.fragmentation_ratio(&stats(3000)), Some(1.0));
+        assert_eq!(report.fragmentation_ratio(&stats(1000)), Some(3.0));
+    }
+}
@@ -1,11 +1,22 @@
 use std::{
-    mem,
-    sync::atomic::{AtomicBool, Ordering},
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    mem, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 
+use backtrace::{Backtrace, BacktraceFrame, Frame};
+use derive_builder::Builder;
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+use super::mallinfo::{Mallinfo, MallinfoReport};
+use super::rss::{RssReport, RssSample};
+
 #[derive(Debug, Default, Clone, Display, Serialize, Deserialize)]
 #[display(fmt = r#"Currently allocated (B): {current}
 Maximum allocated (B): {peak}
@@ -21,7 +32,75 @@ pub struct MemoryStats {
     pub reallocs: usize,
 }
 
-static mut TRACE_ALLOCS: AtomicBool = AtomicBool::new(false);
+/// Options controlling what is recorded by [`trace_allocs_with`].
+#[derive(Debug, Clone, Default, Builder)]
+pub struct TraceOptions {
+    /// Keep a copy of the live-allocation table each time a new peak is
+    /// reached. Every allocation captures a backtrace, so this is slow.
+    #[builder(default)]
+    pub capture_peak: bool,
+    /// Sample process RSS before and after the traced closure.
+    #[builder(default)]
+    pub sample_rss: bool,
+    /// Record glibc allocator statistics before and after the traced
+    /// closure. Ignored on other platforms.
+    #[builder(default)]
+    pub mallinfo: bool,
+}
+
+/// Everything recorded by [`trace_allocs_with`].
+#[derive(Debug, Default)]
+pub struct TraceReport {
+    pub stats: MemoryStats,
+    /// Allocations live at the moment [`MemoryStats::peak`] was reached, if
+    /// [`TraceOptions::capture_peak`] was set.
+    pub peak_snapshot: Option<PeakSnapshot>,
+    /// Process RSS around the traced closure, if [`TraceOptions::sample_rss`]
+    /// was set and RSS is available on this platform.
+    pub rss: Option<RssReport>,
+    /// glibc allocator statistics around the traced closure, if
+    /// [`TraceOptions::mallinfo`] was set and glibc provides `mallinfo2`.
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub mallinfo: Option<MallinfoReport>,
+}
+
+/// An allocation that was live when the peak was reached.
+#[derive(Debug, Clone)]
+pub struct LiveAllocation {
+    pub size: usize,
+    pub call_site: Backtrace,
+}
+
+/// Live-allocation table at the moment of the peak, largest allocations first.
+#[derive(Debug, Clone, Default)]
+pub struct PeakSnapshot {
+    pub allocations: Vec<LiveAllocation>,
+}
+
+impl PeakSnapshot {
+    pub fn total_size(&self) -> usize {
+        self.allocations.iter().map(|a| a.size).sum()
+    }
+}
+
+impl std::fmt::Display for PeakSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} allocations live at peak ({} B):",
+            self.allocations.len(),
+            self.total_size()
+        )?;
+        for alloc in &self.allocations {
+            writeln!(f, "{} B allocated at:\n{:?}", alloc.size, alloc.call_site)?;
+        }
+        Ok(())
+    }
+}
+
+static TRACE_ALLOCS: AtomicBool = AtomicBool::new(false);
+
+static CAPTURE_PEAK: AtomicBool = AtomicBool::new(false);
 
 static mut ALLOC_STATS: MemoryStats = MemoryStats {
     current: 0,
@@ -31,15 +110,223 @@ static mut ALLOC_STATS: MemoryStats = MemoryStats {
     reallocs: 0,
 };
 
+thread_local! {
+    /// Set while the hooks do their own bookkeeping, so that allocations made
+    /// by the bookkeeping itself are neither traced nor recursed into.
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+fn in_hook() -> bool {
+    IN_HOOK.with(Cell::get)
+}
+
+/// Runs `f` as bookkeeping of the hooks. Can be nested.
+pub(crate) fn with_hook_guard<F: FnOnce() -> O, O>(f: F) -> O {
+    let outer = IN_HOOK.with(|h| h.replace(true));
+    let o = f();
+    IN_HOOK.with(|h| h.set(outer));
+    o
+}
+
+/// A live allocation with its unresolved call stack.
+///
+/// Only raw frames are recorded from inside the allocator: capturing a
+/// `std::backtrace::Backtrace` takes the lock std's panic hook holds while
+/// allocating, which deadlocks any panic in the traced closure.
+struct LiveEntry {
+    size: usize,
+    frames: Vec<Frame>,
+}
+
+impl LiveEntry {
+    fn capture(size: usize) -> Self {
+        LiveEntry {
+            size,
+            frames: capture_frames(),
+        }
+    }
+
+    fn resolve(self) -> LiveAllocation {
+        LiveAllocation {
+            size: self.size,
+            call_site: resolve_frames(self.frames),
+        }
+    }
+}
+
+/// Records the raw frames of the current call stack, without symbol
+/// resolution, which would lock and allocate. Safe to call from the hooks.
+pub(crate) fn capture_frames() -> Vec<Frame> {
+    let mut frames = Vec::new();
+    unsafe {
+        backtrace::trace_unsynchronized(|frame| {
+            frames.push(frame.clone());
+            true
+        })
+    };
+    frames
+}
+
+/// Resolves frames recorded by [`capture_frames`]. Must be called outside of
+/// the hooks.
+pub(crate) fn resolve_frames(frames: Vec<Frame>) -> Backtrace {
+    let frames = frames.into_iter().map(BacktraceFrame::from);
+    let mut backtrace = Backtrace::from(frames.collect::<Vec<_>>());
+    backtrace.resolve();
+    backtrace
+}
+
+/// Live allocations, with the state at the last peak kept up to date lazily:
+/// changes made since the peak are accumulated in `pending` and only applied
+/// to `at_peak` when a new peak is reached.
+#[derive(Default)]
+struct LiveTable {
+    at_peak: HashMap<usize, LiveEntry>,
+    pending: HashMap<usize, Option<LiveEntry>>,
+}
+
+impl LiveTable {
+    fn on_alloc(&mut self, pointer: *mut u8, size: usize, new_peak: bool) {
+        let alloc = LiveEntry::capture(size);
+        self.pending.insert(pointer as usize, Some(alloc));
+        if new_peak {
+            for (pointer, alloc) in self.pending.drain() {
+                match alloc {
+                    Some(alloc) => self.at_peak.insert(pointer, alloc),
+                    None => self.at_peak.remove(&pointer),
+                };
+            }
+        }
+    }
+
+    fn on_dealloc(&mut self, pointer: *mut u8) {
+        let pointer = pointer as usize;
+        if self.at_peak.contains_key(&pointer) {
+            self.pending.insert(pointer, None);
+        } else {
+            self.pending.remove(&pointer);
+        }
+    }
+
+    /// Resolves the symbols of the call sites, which must not be done while
+    /// tracing.
+    fn into_snapshot(self) -> PeakSnapshot {
+        let mut allocations = self
+            .at_peak
+            .into_values()
+            .map(LiveEntry::resolve)
+            .collect::<Vec<_>>();
+        allocations.sort_by_key(|a| std::cmp::Reverse(a.size));
+        PeakSnapshot { allocations }
+    }
+}
+
+static LIVE_TABLE: Mutex<Option<LiveTable>> = Mutex::new(None);
+
+fn take_live_table() -> Option<LiveTable> {
+    with_hook_guard(|| LIVE_TABLE.lock().unwrap_or_else(|e| e.into_inner()).take())
+}
+
+/// Ends the tracing scope when dropped, so that a panic in the traced
+/// closure doesn't leave tracing enabled.
+struct TraceScope;
+
+impl Drop for TraceScope {
+    fn drop(&mut self) {
+        CAPTURE_PEAK.store(false, Ordering::Release);
+        with_hook_guard(|| {
+            drop(take_live_table());
+            unsafe { *ptr::addr_of_mut!(ALLOC_STATS) = MemoryStats::default() };
+        });
+        TRACE_ALLOCS.store(false, Ordering::Release);
+    }
+}
+
+/// Set while the setup of a traced closure runs, so that its allocations are
+/// recorded in `SETUP_ALLOCS`.
+static RECORD_SETUP: AtomicBool = AtomicBool::new(false);
+
+/// Set from the start of a setup to the end of the trace following it.
+static TRACK_SETUP: AtomicBool = AtomicBool::new(false);
+
+/// Live allocations made by the setup. Freeing them inside the trace must not
+/// lower [`MemoryStats::current`], which only counts traced allocations.
+static SETUP_ALLOCS: Mutex<Option<HashSet<usize>>> = Mutex::new(None);
+
+/// Serializes users of `SETUP_ALLOCS`.
+static SETUP_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_setup_allocs<F: FnOnce(&mut HashSet<usize>) -> O, O: Default>(f: F) -> O {
+    with_hook_guard(|| {
+        SETUP_ALLOCS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+            .map(f)
+            .unwrap_or_default()
+    })
+}
+
+/// Tracks the allocations of a setup until dropped.
+struct SetupScope {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl SetupScope {
+    fn record() -> Self {
+        let lock = SETUP_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        with_hook_guard(|| {
+            *SETUP_ALLOCS.lock().unwrap_or_else(|e| e.into_inner()) = Some(HashSet::new())
+        });
+        TRACK_SETUP.store(true, Ordering::Release);
+        RECORD_SETUP.store(true, Ordering::Release);
+        SetupScope { _lock: lock }
+    }
+
+    fn stop_recording(&self) {
+        RECORD_SETUP.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for SetupScope {
+    fn drop(&mut self) {
+        RECORD_SETUP.store(false, Ordering::Release);
+        TRACK_SETUP.store(false, Ordering::Release);
+        with_hook_guard(|| {
+            drop(
+                SETUP_ALLOCS
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take(),
+            )
+        });
+    }
+}
+
+fn with_live_table<F: FnOnce(&mut LiveTable)>(f: F) {
+    with_hook_guard(|| {
+        if let Some(table) = LIVE_TABLE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            f(table);
+        }
+    })
+}
+
 /// Traces allocations performed while executing the `f`.
 ///
 /// Beware that allocations made by nother threads will be also recorded.
 ///
 /// ```
-/// use tracing_allocator::{TracingAllocator, default_tracing_allocator, trace_allocs};
+/// use std::alloc::System;
+/// use alloc_test::alloc::{
+///     allocator::TracingAllocator, default_tracing_allocator, measure::{trace_allocs, MemoryTracingHooks},
+/// };
 ///
 /// #[global_allocator]
-/// static ALLOCATOR: TracingAllocator = default_tracing_allocator();
+/// static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
 ///
 /// fn main() {
 ///     let (_, stats) = trace_allocs(|| {
@@ -51,40 +338,153 @@ static mut ALLOC_STATS: MemoryStats = MemoryStats {
 /// }
 /// ```
 pub fn trace_allocs<F: FnOnce() -> O, O>(f: F) -> (O, MemoryStats) {
-    unsafe {
-        while TRACE_ALLOCS
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Acquire)
-            .is_err()
-        {}
-        let o = f();
-        let stats = mem::replace(&mut ALLOC_STATS, Default::default());
-        TRACE_ALLOCS.store(false, Ordering::Release);
-        (o, stats)
+    let (o, report) = trace_allocs_with(&TraceOptions::default(), f);
+    (o, report.stats)
+}
+
+/// Like [`trace_allocs`], but `f` takes an input built by `setup`, which is
+/// not traced.
+///
+/// Deallocations of memory allocated by `setup` are ignored, so that `f`
+/// consuming its input doesn't lower the stats.
+pub fn trace_allocs_with_setup<I, O, S, F>(setup: S, f: F) -> (O, MemoryStats)
+where
+    S: FnOnce() -> I,
+    F: FnOnce(I) -> O,
+{
+    let scope = SetupScope::record();
+    let input = setup();
+    scope.stop_recording();
+    trace_allocs(|| f(input))
+}
+
+/// Traces allocations performed while executing the `f`, recording
+/// additional information according to `options`.
+///
+/// ```
+/// use std::alloc::System;
+/// use alloc_test::alloc::{
+///     allocator::TracingAllocator, default_tracing_allocator,
+///     measure::{trace_allocs_with, MemoryTracingHooks, TraceOptionsBuilder},
+/// };
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+///
+/// fn main() {
+///     let options = TraceOptionsBuilder::default().capture_peak(true).build().unwrap();
+///     let (_, report) = trace_allocs_with(&options, || {
+///         let a: Vec<u8> = vec![0; 100];
+///         let b: Vec<u8> = vec![0; 10];
+///         drop(a);
+///         b
+///     });
+///     let snapshot = report.peak_snapshot.unwrap();
+///     assert_eq!(report.stats.peak, 110);
+///     assert_eq!(snapshot.allocations.len(), 2);
+///     assert_eq!(snapshot.allocations[0].size, 100);
+/// }
+/// ```
+pub fn trace_allocs_with<F: FnOnce() -> O, O>(options: &TraceOptions, f: F) -> (O, TraceReport) {
+    #[cfg(target_os = "linux")]
+    register_preload_hooks();
+    let rss_before = options.sample_rss.then(RssSample::now).flatten();
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    let mallinfo_before = options.mallinfo.then(Mallinfo::now).flatten();
+    while TRACE_ALLOCS
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Acquire)
+        .is_err()
+    {}
+    let scope = TraceScope;
+    if options.capture_peak {
+        with_hook_guard(|| {
+            *LIVE_TABLE.lock().unwrap_or_else(|e| e.into_inner()) = Some(LiveTable::default())
+        });
+        CAPTURE_PEAK.store(true, Ordering::Release);
     }
+    let o = f();
+    CAPTURE_PEAK.store(false, Ordering::Release);
+    let live_table = take_live_table();
+    let stats = unsafe { mem::take(&mut *ptr::addr_of_mut!(ALLOC_STATS)) };
+    drop(scope);
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    let mallinfo = mallinfo_before
+        .zip(Mallinfo::now())
+        .map(|(before, after)| MallinfoReport { before, after });
+    let rss = rss_before
+        .zip(RssSample::now())
+        .map(|(before, after)| RssReport { before, after });
+    let report = TraceReport {
+        stats,
+        peak_snapshot: live_table.map(LiveTable::into_snapshot),
+        rss,
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        mallinfo,
+    };
+    (o, report)
+}
+
+/// Statistics recorded so far by the enclosing [`trace_allocs`] scope.
+pub(crate) fn current_stats() -> MemoryStats {
+    unsafe { (*ptr::addr_of!(ALLOC_STATS)).clone() }
 }
 
 pub struct MemoryTracingHooks;
 
+/// Makes C heap operations count towards [`MemoryStats`] if the preload shim
+/// is loaded.
+#[cfg(target_os = "linux")]
+fn register_preload_hooks() {
+    static HOOKS: MemoryTracingHooks = MemoryTracingHooks;
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(|| {
+        super::preload::register(&HOOKS);
+    });
+}
+
 unsafe impl super::allocator::AllocHooks for MemoryTracingHooks {
-    fn on_alloc(&self, _pointer: *mut u8, size: usize, _align: usize) {
-        unsafe {
-            if TRACE_ALLOCS.load(Ordering::Acquire) {
-                // println!("allocating {size}");
-                ALLOC_STATS.current += size;
-                ALLOC_STATS.total_size += size;
-                ALLOC_STATS.total_num += 1;
-                if ALLOC_STATS.current > ALLOC_STATS.peak {
-                    ALLOC_STATS.peak = ALLOC_STATS.current;
-                }
+    fn on_alloc(&self, pointer: *mut u8, size: usize, _align: usize) {
+        if in_hook() {
+            return;
+        }
+        super::no_alloc::on_alloc(size);
+        if RECORD_SETUP.load(Ordering::Acquire) {
+            with_setup_allocs(|allocs| allocs.insert(pointer as usize));
+        }
+        if !TRACE_ALLOCS.load(Ordering::Acquire) {
+            return;
+        }
+        let new_peak = unsafe {
+            // println!("allocating {size}");
+            ALLOC_STATS.current += size;
+            ALLOC_STATS.total_size += size;
+            ALLOC_STATS.total_num += 1;
+            if ALLOC_STATS.current > ALLOC_STATS.peak {
+                ALLOC_STATS.peak = ALLOC_STATS.current;
+                true
+            } else {
+                false
             }
+        };
+        if CAPTURE_PEAK.load(Ordering::Acquire) {
+            with_live_table(|table| table.on_alloc(pointer, size, new_peak));
         }
     }
 
-    fn on_dealloc(&self, _pointer: *mut u8, size: usize, _align: usize) {
+    fn on_dealloc(&self, pointer: *mut u8, size: usize, _align: usize) {
+        if in_hook() {
+            return;
+        }
+        let from_setup = TRACK_SETUP.load(Ordering::Acquire)
+            && with_setup_allocs(|allocs| allocs.remove(&(pointer as usize)));
+        if !TRACE_ALLOCS.load(Ordering::Acquire) || from_setup {
+            return;
+        }
         unsafe {
-            if TRACE_ALLOCS.load(Ordering::Acquire) {
-                ALLOC_STATS.current = ALLOC_STATS.current.saturating_sub(size);
-            }
+            ALLOC_STATS.current = ALLOC_STATS.current.saturating_sub(size);
+        }
+        if CAPTURE_PEAK.load(Ordering::Acquire) {
+            with_live_table(|table| table.on_dealloc(pointer));
         }
     }
 
@@ -100,9 +500,9 @@ unsafe impl super::allocator::AllocHooks for MemoryTracingHooks {
         new_size: usize,
         align: usize,
     ) {
-        unsafe {
-            if TRACE_ALLOCS.load(Ordering::Acquire) {
-                // println!("reallocating {old_size} -> {new_size}");
+        if TRACE_ALLOCS.load(Ordering::Acquire) && !in_hook() {
+            // println!("reallocating {old_size} -> {new_size}");
+            unsafe {
                 ALLOC_STATS.reallocs += 1;
             }
         }
@@ -110,3 +510,17 @@ unsafe impl super::allocator::AllocHooks for MemoryTracingHooks {
         self.on_alloc(new_pointer, new_size, align);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_hook_guard() {
+        with_hook_guard(|| {
+            with_hook_guard(|| assert!(in_hook()));
+            assert!(in_hook());
+        });
+        assert!(!in_hook());
+    }
+}
@@ -5,7 +5,15 @@ use self::{allocator::TracingAllocator, measure::MemoryTracingHooks};
 pub mod allocator;
 pub mod benchmark;
 pub mod compare;
+pub mod determinism;
+pub mod leak;
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub mod mallinfo;
 pub mod measure;
+pub mod no_alloc;
+#[cfg(target_os = "linux")]
+pub mod preload;
+pub mod rss;
 
 pub const fn default_tracing_allocator() -> TracingAllocator<MemoryTracingHooks, System> {
     TracingAllocator::new(MemoryTracingHooks, System)
@@ -0,0 +1,196 @@
+//! Detection of allocations in regions that must not allocate.
+//!
+//! Requires the global allocator to be a
+//! [`TracingAllocator`](super::allocator::TracingAllocator) with
+//! [`MemoryTracingHooks`](super::measure::MemoryTracingHooks). Regions are
+//! per thread.
+//!
+//! ```
+//! use std::alloc::System;
+//! use alloc_test::alloc::{
+//!     allocator::TracingAllocator, default_tracing_allocator, measure::MemoryTracingHooks,
+//!     no_alloc::{assert_no_alloc, NoAllocGuard, NoAllocMode},
+//! };
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+//!
+//! fn main() {
+//!     let mut v = Vec::with_capacity(4);
+//!     assert_no_alloc(|| v.push(1));
+//!
+//!     let guard = NoAllocGuard::new(NoAllocMode::Count);
+//!     v.extend([2, 3, 4, 5]);
+//!     assert_eq!(guard.allocations(), 1);
+//! }
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    thread,
+};
+
+use backtrace::Frame;
+
+use super::measure::{capture_frames, resolve_frames, with_hook_guard};
+
+/// What happens when a no-alloc region allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoAllocMode {
+    /// Capture a backtrace of the first allocation and panic with it when the
+    /// region ends. Unwinding out of the global allocator is undefined
+    /// behaviour, so the panic cannot be raised at the allocation itself.
+    Panic,
+    /// Capture a backtrace of the first allocation and log it when the region
+    /// ends. Printing from inside the global allocator would allocate.
+    Log,
+    /// Only count allocations. Cheap enough for release builds.
+    Count,
+}
+
+#[derive(Debug)]
+struct Region {
+    allocations: usize,
+    /// Whether this region or an enclosing one reports its first allocation,
+    /// and so needs its frames.
+    capture: bool,
+    /// Size and unresolved frames of the first allocation, if captured.
+    first: Option<(usize, Vec<Frame>)>,
+}
+
+thread_local! {
+    static REGION: RefCell<Option<Region>> = const { RefCell::new(None) };
+    /// Whether `REGION` holds a region. Has no destructor, so unlike `REGION`
+    /// it can be read from the hooks at any point of a thread's life: the
+    /// first access to `REGION` registers its destructor, which calls
+    /// `calloc`, and with the preload shim loaded reading it from the hooks
+    /// would recurse into that registration.
+    static IN_REGION: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Called by the allocation hooks for each allocation.
+pub(crate) fn on_alloc(size: usize) {
+    if !IN_REGION.with(Cell::get) {
+        return;
+    }
+    let capture = REGION
+        .try_with(|r| {
+            let mut r = r.borrow_mut();
+            let Some(region) = r.as_mut() else {
+                return false;
+            };
+            region.allocations += 1;
+            region.allocations == 1 && region.capture
+        })
+        .unwrap_or(false);
+    if capture {
+        let frames = with_hook_guard(capture_frames);
+        let _ = REGION.try_with(|r| {
+            if let Some(region) = r.borrow_mut().as_mut() {
+                region.first = Some((size, frames));
+            }
+        });
+    }
+}
+
+/// Marks the current thread as not allowed to allocate until dropped.
+///
+/// Guards can be nested; allocations made inside an inner guard also count
+/// towards the outer one, and an outer [`NoAllocMode::Panic`] guard panics
+/// with the backtrace of the first of them.
+#[derive(Debug)]
+pub struct NoAllocGuard {
+    mode: NoAllocMode,
+    outer: Option<Region>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl NoAllocGuard {
+    pub fn new(mode: NoAllocMode) -> Self {
+        let outer = REGION.with(|r| {
+            let mut r = r.borrow_mut();
+            let capture = mode != NoAllocMode::Count || r.as_ref().is_some_and(|o| o.capture);
+            // `Region` holds no heap data yet, so this doesn't allocate
+            r.replace(Region {
+                allocations: 0,
+                capture,
+                first: None,
+            })
+        });
+        IN_REGION.with(|r| r.set(true));
+        NoAllocGuard {
+            mode,
+            outer,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Number of allocations made so far in this region.
+    pub fn allocations(&self) -> usize {
+        REGION.with(|r| r.borrow().as_ref().map_or(0, |r| r.allocations))
+    }
+}
+
+impl Drop for NoAllocGuard {
+    fn drop(&mut self) {
+        let region = REGION.with(|r| r.borrow_mut().take());
+        IN_REGION.with(|r| r.set(self.outer.is_some()));
+        let Some(Region {
+            allocations, first, ..
+        }) = region
+        else {
+            return;
+        };
+        let first = match self.outer.take() {
+            Some(mut outer) => {
+                outer.allocations += allocations;
+                // a panicking guard consumes its own backtrace, a logging one
+                // leaves it to enclosing guards too
+                let first = match self.mode {
+                    NoAllocMode::Panic => first,
+                    NoAllocMode::Log => {
+                        outer.first = outer.first.take().or_else(|| first.clone());
+                        first
+                    }
+                    NoAllocMode::Count => {
+                        outer.first = outer.first.take().or(first);
+                        None
+                    }
+                };
+                REGION.with(|r| *r.borrow_mut() = Some(outer));
+                first
+            }
+            None => first,
+        };
+        if allocations == 0 {
+            return;
+        }
+        // reporting allocates, which must not count towards enclosing regions
+        match self.mode {
+            NoAllocMode::Panic if !thread::panicking() => {
+                let report = with_hook_guard(|| report(allocations, first));
+                panic!("{report}")
+            }
+            NoAllocMode::Log => with_hook_guard(|| log!("{}", report(allocations, first))),
+            _ => {}
+        }
+    }
+}
+
+fn report(allocations: usize, first: Option<(usize, Vec<Frame>)>) -> String {
+    let first = first.map_or_else(
+        || "at <unavailable>".to_string(),
+        |(size, frames)| format!("of {size} B at:\n{:?}", resolve_frames(frames)),
+    );
+    format!("{allocations} allocations in a no-alloc region, the first one {first}")
+}
+
+/// Runs `f`, panicking if it allocates. The panic message contains the
+/// backtrace of the first allocation.
+pub fn assert_no_alloc<F: FnOnce() -> O, O>(f: F) -> O {
+    let guard = NoAllocGuard::new(NoAllocMode::Panic);
+    let o = f();
+    drop(guard);
+    o
+}
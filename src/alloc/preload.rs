@@ -0,0 +1,101 @@
+//! Tracing of C heap operations intercepted by the `alloc-test-preload` shim.
+//!
+//! When the process runs with `liballoc_test_preload.so` in `LD_PRELOAD`,
+//! `malloc`, `free` and friends called by linked C libraries are forwarded to
+//! [`AllocHooks`], so that [`MemoryStats`](super::measure::MemoryStats) covers
+//! the whole process. Operations made on behalf of a [`TracingAllocator`] are
+//! skipped, as its own hooks have already seen them.
+//!
+//! The shim also reports the operations of glibc and std while a thread is
+//! set up or torn down, so the hooks must not touch thread-locals that have
+//! destructors unless they know them to be initialized and alive: the first
+//! access registers the destructor, which itself calls `calloc`.
+//!
+//! [`TracingAllocator`]: super::allocator::TracingAllocator
+
+use std::ffi::c_void;
+
+use super::allocator::{in_tracing_allocator, AllocHooks};
+
+/// Callbacks passed to the shim.
+///
+/// Must match `alloc_test_preload::PreloadHooks`.
+#[repr(C)]
+struct PreloadHooks {
+    ctx: *const c_void,
+    on_alloc: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    on_dealloc: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    on_alloc_zeroed: unsafe extern "C" fn(*const c_void, *mut u8, usize, usize),
+    on_realloc: unsafe extern "C" fn(*const c_void, *mut u8, *mut u8, usize, usize, usize),
+}
+
+unsafe extern "C" fn on_alloc<H: AllocHooks>(
+    ctx: *const c_void,
+    pointer: *mut u8,
+    size: usize,
+    align: usize,
+) {
+    if !in_tracing_allocator() {
+        (*ctx.cast::<H>()).on_alloc(pointer, size, align);
+    }
+}
+
+unsafe extern "C" fn on_dealloc<H: AllocHooks>(
+    ctx: *const c_void,
+    pointer: *mut u8,
+    size: usize,
+    align: usize,
+) {
+    if !in_tracing_allocator() {
+        (*ctx.cast::<H>()).on_dealloc(pointer, size, align);
+    }
+}
+
+unsafe extern "C" fn on_alloc_zeroed<H: AllocHooks>(
+    ctx: *const c_void,
+    pointer: *mut u8,
+    size: usize,
+    align: usize,
+) {
+    if !in_tracing_allocator() {
+        (*ctx.cast::<H>()).on_alloc_zeroed(pointer, size, align);
+    }
+}
+
+unsafe extern "C" fn on_realloc<H: AllocHooks>(
+    ctx: *const c_void,
+    old_pointer: *mut u8,
+    new_pointer: *mut u8,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) {
+    if !in_tracing_allocator() {
+        (*ctx.cast::<H>()).on_realloc(old_pointer, new_pointer, old_size, new_size, align);
+    }
+}
+
+type RegisterFn = unsafe extern "C" fn(*const PreloadHooks);
+
+/// Forwards C heap operations intercepted by the preload shim to `hooks`.
+///
+/// Returns `false` if the shim is not loaded into the process.
+pub fn register<H: AllocHooks + Sync>(hooks: &'static H) -> bool {
+    let register =
+        unsafe { libc::dlsym(libc::RTLD_DEFAULT, c"alloc_test_preload_register".as_ptr()) };
+    if register.is_null() {
+        return false;
+    }
+    let hooks = Box::leak(Box::new(PreloadHooks {
+        ctx: (hooks as *const H).cast(),
+        on_alloc: on_alloc::<H>,
+        on_dealloc: on_dealloc::<H>,
+        on_alloc_zeroed: on_alloc_zeroed::<H>,
+        on_realloc: on_realloc::<H>,
+    }));
+    unsafe {
+        let register = std::mem::transmute::<*mut c_void, RegisterFn>(register);
+        register(hooks);
+    }
+    true
+}
@@ -0,0 +1,107 @@
+use std::fs;
+
+use derive_more::Display;
+
+use super::measure::MemoryStats;
+
+/// Resident set size of the process, as reported by `/proc/self/status`.
+#[derive(Debug, Clone, Copy, Default, Display)]
+#[display(fmt = "{rss} B (peak {peak_rss} B)")]
+pub struct RssSample {
+    /// `VmRSS`, in bytes.
+    pub rss: usize,
+    /// `VmHWM`, in bytes.
+    pub peak_rss: usize,
+}
+
+impl RssSample {
+    /// Samples the current process. Returns `None` where `/proc` is not
+    /// available.
+    pub fn now() -> Option<Self> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.trim().strip_suffix("kB"))
+                .and_then(|kb| kb.trim().parse::<usize>().ok())
+                .map(|kb| kb * 1024)
+        };
+        Some(RssSample {
+            rss: field("VmRSS")?,
+            peak_rss: field("VmHWM")?,
+        })
+    }
+}
+
+/// Growth of untraced memory larger than this is reported as a gap.
+const GAP_THRESHOLD: isize = 1024 * 1024;
+
+/// Process RSS before and after a traced closure.
+#[derive(Debug, Clone, Copy, Default, Display)]
+#[display(fmt = r#"RSS before: {before}
+RSS after: {after}
+"#)]
+pub struct RssReport {
+    pub before: RssSample,
+    pub after: RssSample,
+}
+
+impl RssReport {
+    pub fn rss_growth(&self) -> isize {
+        self.after.rss as isize - self.before.rss as isize
+    }
+
+    pub fn peak_rss_growth(&self) -> isize {
+        self.after.peak_rss as isize - self.before.peak_rss as isize
+    }
+
+    /// RSS growth not accounted for by the traced heap, e.g. caused by
+    /// `mmap`, C allocations or allocator fragmentation.
+    pub fn untraced_growth(&self, stats: &MemoryStats) -> isize {
+        self.rss_growth() - stats.current as isize
+    }
+
+    /// Peak RSS growth not accounted for by the traced heap peak.
+    pub fn untraced_peak_growth(&self, stats: &MemoryStats) -> isize {
+        self.peak_rss_growth() - stats.peak as isize
+    }
+
+    /// Whether RSS grew by considerably more than the traced heap, meaning
+    /// that the allocation hooks miss a significant part of memory usage.
+    pub fn has_gap(&self, stats: &MemoryStats) -> bool {
+        let large = |gap: isize, traced: usize| gap > GAP_THRESHOLD.max(traced as isize);
+        large(self.untraced_growth(stats), stats.current)
+            || large(self.untraced_peak_growth(stats), stats.peak)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rss_gap() {
+        let mib = 1024 * 1024;
+        let rss = |rss, peak_rss| RssSample { rss, peak_rss };
+        let stats = MemoryStats {
+            current: 2 * mib,
+            peak: 4 * mib,
+            ..Default::default()
+        };
+
+        let report = RssReport {
+            before: rss(10 * mib, 10 * mib),
+            after: rss(12 * mib, 14 * mib),
+        };
+        assert_eq!(report.untraced_growth(&stats), 0);
+        assert!(!report.has_gap(&stats));
+
+        let report = RssReport {
+            before: rss(10 * mib, 10 * mib),
+            after: rss(20 * mib, 20 * mib),
+        };
+        assert_eq!(report.untraced_growth(&stats), 8 * mib as isize);
+        assert!(report.has_gap(&stats));
+    }
+}
@@ -0,0 +1,166 @@
+//! Allocation and performance benchmarks combined, sharing a single baseline.
+
+use derive_builder::Builder;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    alloc::{
+        compare::{AllocThresholds, AllocThresholdsBuilder, AllocThresholdsError},
+        measure::{trace_allocs, MemoryStats},
+    },
+    perf::{
+        compare::{PerfThresholds, PerfThresholdsBuilder, PerfThresholdsError},
+        measure::{bench, PerfStats},
+    },
+    threshold::ThresholdFor,
+};
+
+#[derive(Debug, Default, Clone, Display, Serialize, Deserialize)]
+#[display(fmt = "{alloc}{perf}")]
+pub struct BenchStats {
+    pub alloc: MemoryStats,
+    pub perf: PerfStats,
+}
+
+/// Limits for both allocation and performance statistics.
+#[derive(Debug, Builder)]
+pub struct BenchThresholds {
+    #[builder(default = "AllocThresholdsBuilder::default().build().unwrap()")]
+    pub alloc: AllocThresholds,
+    #[builder(default = "PerfThresholdsBuilder::default().build().unwrap()")]
+    pub perf: PerfThresholds,
+}
+
+#[derive(Debug, Error)]
+pub enum BenchThresholdsError {
+    #[error(transparent)]
+    Alloc(#[from] AllocThresholdsError),
+    #[error(transparent)]
+    Perf(#[from] PerfThresholdsError),
+}
+
+impl ThresholdFor<BenchStats> for BenchThresholds {
+    type Error = BenchThresholdsError;
+
+    fn check_threshold(
+        &self,
+        value: &BenchStats,
+        ref_value: &BenchStats,
+    ) -> Result<(), Self::Error> {
+        self.alloc.check(&value.alloc, &ref_value.alloc)?;
+        self.perf.check_threshold(&value.perf, &ref_value.perf)?;
+        Ok(())
+    }
+}
+
+pub fn benchmark<F: Fn() -> O, O>(id: &str, f: F) -> BenchStats {
+    let (_, alloc) = trace_allocs(&f);
+    let perf = bench(&f);
+    let stats = BenchStats { alloc, perf };
+    log!("\nbenchmark stats for `{id}`:\n{stats}");
+    stats
+}
+
+/// Checks both allocations and performance of a benchmark against its
+/// baseline.
+///
+/// Accepts the same forms as [`alloc_bench!`](crate::alloc_bench), with
+/// [`BenchThresholds`] as thresholds.
+#[macro_export]
+macro_rules! bench {
+    ($test:ident, $thresh:expr) => {
+        $crate::bench!(stringify!($test), $test, $thresh)
+    };
+    ($id:expr, $f:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let f = $f;
+        $crate::threshold::check_threshold_with_args(
+            || $crate::bench::benchmark(id, &f),
+            "bench",
+            id,
+            $thresh,
+        )
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threshold::Threshold;
+
+    #[test]
+    fn thresholds() {
+        let rs = BenchStats {
+            alloc: MemoryStats {
+                peak: 100,
+                ..Default::default()
+            },
+            perf: PerfStats {
+                mean_ns: 100,
+                ..Default::default()
+            },
+        };
+        let vs = BenchStats {
+            alloc: MemoryStats {
+                peak: 200,
+                ..Default::default()
+            },
+            perf: PerfStats {
+                mean_ns: 200,
+                ..Default::default()
+            },
+        };
+        let stats = toml::to_string(&vs).unwrap();
+        assert_eq!(
+            toml::from_str::<BenchStats>(&stats).unwrap().perf.mean_ns,
+            200
+        );
+
+        let ts = BenchThresholdsBuilder::default()
+            .perf(
+                PerfThresholdsBuilder::default()
+                    .mean_ns(Threshold::cap(50))
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(matches!(
+            ts.check_threshold(&vs, &rs),
+            Err(BenchThresholdsError::Perf(_))
+        ));
+
+        let ts = BenchThresholdsBuilder::default()
+            .alloc(
+                AllocThresholdsBuilder::default()
+                    .peak(Threshold::cap(50))
+                    .build()
+                    .unwrap(),
+            )
+            .perf(
+                PerfThresholdsBuilder::default()
+                    .mean_ns(Threshold::cap(50))
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(matches!(
+            ts.check_threshold(&vs, &rs),
+            Err(BenchThresholdsError::Alloc(_))
+        ));
+        // performance isn't checked unless asked for
+        let ts = BenchThresholdsBuilder::default()
+            .alloc(
+                AllocThresholdsBuilder::default()
+                    .peak(Threshold::cap(100))
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(ts.check_threshold(&vs, &rs).is_ok());
+    }
+}
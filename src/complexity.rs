@@ -0,0 +1,347 @@
+//! Asymptotic complexity checks.
+//!
+//! A function is benchmarked over a series of input sizes, and the growth of
+//! each statistic is fitted against common complexity classes, so that e.g.
+//! an accidental quadratic clone is caught even if the single size used by a
+//! baseline comparison stays within its threshold.
+
+use derive_more::Display;
+use thiserror::Error;
+
+use crate::{
+    alloc::measure::{trace_allocs_with_setup, MemoryStats},
+    perf::measure::{bench_with_setup, PerfStats},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+pub enum Complexity {
+    #[display(fmt = "O(1)")]
+    Constant,
+    #[display(fmt = "O(log n)")]
+    Logarithmic,
+    #[display(fmt = "O(n)")]
+    Linear,
+    #[display(fmt = "O(n log n)")]
+    Linearithmic,
+    #[display(fmt = "O(n^2)")]
+    Quadratic,
+}
+
+impl Complexity {
+    const ALL: [Complexity; 5] = [
+        Complexity::Constant,
+        Complexity::Logarithmic,
+        Complexity::Linear,
+        Complexity::Linearithmic,
+        Complexity::Quadratic,
+    ];
+
+    fn model(self, n: f64) -> f64 {
+        match self {
+            Complexity::Constant => 1.0,
+            Complexity::Logarithmic => n.ln(),
+            Complexity::Linear => n,
+            Complexity::Linearithmic => n * n.ln(),
+            Complexity::Quadratic => n * n,
+        }
+    }
+}
+
+/// Statistic whose growth is fitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Metric {
+    #[display(fmt = "current")]
+    Current,
+    #[display(fmt = "peak")]
+    Peak,
+    #[display(fmt = "total_size")]
+    TotalSize,
+    #[display(fmt = "total_num")]
+    TotalNum,
+    #[display(fmt = "reallocs")]
+    Reallocs,
+    #[display(fmt = "mean")]
+    Mean,
+}
+
+/// Stats measured for a single input size.
+#[derive(Debug, Clone)]
+pub struct SizePoint {
+    pub n: usize,
+    pub alloc: MemoryStats,
+    pub perf: PerfStats,
+}
+
+impl SizePoint {
+    pub fn get(&self, metric: Metric) -> f64 {
+        (match metric {
+            Metric::Current => self.alloc.current,
+            Metric::Peak => self.alloc.peak,
+            Metric::TotalSize => self.alloc.total_size,
+            Metric::TotalNum => self.alloc.total_num,
+            Metric::Reallocs => self.alloc.reallocs,
+            Metric::Mean => self.perf.mean_ns as usize,
+        }) as f64
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ComplexityError {
+    #[error("`{metric}` grows as {actual}, expected at most {expected}")]
+    TooFast {
+        metric: Metric,
+        expected: Complexity,
+        actual: Complexity,
+    },
+    /// The growth of the metric fits no complexity class well enough, e.g.
+    /// because the measurements are too noisy.
+    #[error("growth of `{metric}` fits no complexity class, expected at most {expected}")]
+    Undetermined {
+        metric: Metric,
+        expected: Complexity,
+    },
+}
+
+/// Stats measured over a series of input sizes.
+#[derive(Debug, Clone)]
+pub struct ComplexityReport {
+    pub points: Vec<SizePoint>,
+}
+
+impl ComplexityReport {
+    /// Complexity class best describing the growth of `metric`, if any
+    /// describes it well enough.
+    pub fn fit(&self, metric: Metric) -> Option<Complexity> {
+        let points = self
+            .points
+            .iter()
+            .map(|p| (p.n as f64, p.get(metric)))
+            .collect::<Vec<_>>();
+        fit(&points)
+    }
+
+    /// Checks that `metric` grows no faster than `expected`. A growth that
+    /// can't be determined fails the check.
+    pub fn check(&self, metric: Metric, expected: Complexity) -> Result<(), ComplexityError> {
+        match self.fit(metric) {
+            Some(actual) if actual <= expected => Ok(()),
+            Some(actual) => Err(ComplexityError::TooFast {
+                metric,
+                expected,
+                actual,
+            }),
+            None => Err(ComplexityError::Undetermined { metric, expected }),
+        }
+    }
+}
+
+/// Values that vary by less than this fraction of their mean are considered
+/// constant.
+const CONSTANT_SPREAD: f64 = 0.05;
+
+/// Fits are considered meaningless below this coefficient of determination.
+const MIN_R2: f64 = 0.8;
+
+/// Fewer sizes can't tell complexity classes apart.
+const MIN_SIZES: usize = 3;
+
+fn fit(points: &[(f64, f64)]) -> Option<Complexity> {
+    if points.len() < MIN_SIZES {
+        return None;
+    }
+    let ys = points.iter().map(|&(_, y)| y);
+    let (min, max) = ys.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), y| {
+        (min.min(y), max.max(y))
+    });
+    let mean_y = points.iter().map(|&(_, y)| y).sum::<f64>() / points.len() as f64;
+    if max - min <= CONSTANT_SPREAD * mean_y.abs() {
+        return Some(Complexity::Constant);
+    }
+
+    // least squares weighted by 1 / y^2, i.e. on relative errors: the noise
+    // of measurements grows with their value, and unweighted fits, dominated
+    // by the largest sizes, confuse O(n) with O(n log n)
+    let weights = points
+        .iter()
+        .map(|&(_, y)| y.abs().max(1.0).powi(-2))
+        .collect::<Vec<_>>();
+    let weighted_mean = |values: &[f64]| {
+        values.iter().zip(&weights).map(|(v, w)| v * w).sum::<f64>() / weights.iter().sum::<f64>()
+    };
+    let ys = points.iter().map(|&(_, y)| y).collect::<Vec<_>>();
+    let mean_y = weighted_mean(&ys);
+    let ss_tot = ys
+        .iter()
+        .zip(&weights)
+        .map(|(y, w)| w * (y - mean_y).powi(2))
+        .sum::<f64>();
+    let r2 = |complexity: Complexity| {
+        let gs = points
+            .iter()
+            .map(|&(n, _)| complexity.model(n))
+            .collect::<Vec<_>>();
+        let mean_g = weighted_mean(&gs);
+        let (cov, var) =
+            gs.iter()
+                .zip(&ys)
+                .zip(&weights)
+                .fold((0.0, 0.0), |(cov, var), ((g, y), w)| {
+                    (
+                        cov + w * (g - mean_g) * (y - mean_y),
+                        var + w * (g - mean_g).powi(2),
+                    )
+                });
+        if var == 0.0 || cov <= 0.0 {
+            return 0.0;
+        }
+        // coefficient of determination of `y = a + b * g(n)`
+        cov * cov / var / ss_tot
+    };
+
+    Complexity::ALL[1..]
+        .iter()
+        .map(|&c| (c, r2(c)))
+        .filter(|&(_, r2)| r2 >= MIN_R2)
+        .fold(
+            None,
+            |best: Option<(Complexity, f64)>, (c, r2)| match best {
+                Some((_, best_r2)) if best_r2 >= r2 => best,
+                _ => Some((c, r2)),
+            },
+        )
+        .map(|(c, _)| c)
+}
+
+/// Benchmarks `f` for each of `sizes`, building its input with `setup`
+/// outside of the measured region. At least three sizes are needed.
+///
+/// ```ignore
+/// let report = measure_complexity(&[1000, 2000, 4000, 8000], |n| vec![0_u8; n], |v| v.clone());
+/// report.check(Metric::Peak, Complexity::Linear).unwrap();
+/// ```
+pub fn measure_complexity<I, O, S, F>(sizes: &[usize], setup: S, f: F) -> ComplexityReport
+where
+    S: Fn(usize) -> I,
+    F: Fn(I) -> O,
+{
+    assert!(sizes.len() >= MIN_SIZES, "Number of sizes is too low");
+    let points = sizes
+        .iter()
+        .map(|&n| {
+            let (output, alloc) = trace_allocs_with_setup(|| setup(n), &f);
+            drop(output);
+            let perf = bench_with_setup(|| setup(n), &f, drop);
+            log!("\nstats for n = {n}:\n{alloc}{perf}");
+            SizePoint { n, alloc, perf }
+        })
+        .collect();
+    ComplexityReport { points }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points<F: Fn(f64) -> f64>(f: F) -> Vec<(f64, f64)> {
+        [100.0, 200.0, 400.0, 800.0, 1600.0, 3200.0, 6400.0]
+            .into_iter()
+            .map(|n| (n, f(n)))
+            .collect()
+    }
+
+    /// Deterministic noise in `[-1, 1]`.
+    fn noise(n: f64) -> f64 {
+        (n * 0.37).sin()
+    }
+
+    #[test]
+    fn fit_complexity() {
+        assert_eq!(fit(&points(|_| 64.0)), Some(Complexity::Constant));
+        assert_eq!(
+            fit(&points(|n| 64.0 + noise(n))),
+            Some(Complexity::Constant)
+        );
+        assert_eq!(
+            fit(&points(|n| 10.0 * n.ln())),
+            Some(Complexity::Logarithmic)
+        );
+        assert_eq!(fit(&points(|n| 24.0 + 8.0 * n)), Some(Complexity::Linear));
+        assert_eq!(fit(&points(|n| n * n.ln())), Some(Complexity::Linearithmic));
+        assert_eq!(fit(&points(|n| n * n / 2.0)), Some(Complexity::Quadratic));
+    }
+
+    #[test]
+    fn fit_noisy_complexity() {
+        for amplitude in [0.01, 0.05, 0.1] {
+            let linear = points(|n| (24.0 + 8.0 * n) * (1.0 + amplitude * noise(n)));
+            assert_eq!(fit(&linear), Some(Complexity::Linear), "{amplitude}");
+            let linearithmic = points(|n| n * n.ln() * (1.0 + amplitude * noise(n)));
+            assert_eq!(
+                fit(&linearithmic),
+                Some(Complexity::Linearithmic),
+                "{amplitude}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_complexity() {
+        let report = ComplexityReport {
+            points: [100, 200, 400, 800]
+                .into_iter()
+                .map(|n| SizePoint {
+                    n,
+                    alloc: MemoryStats {
+                        peak: n * n,
+                        total_num: 1,
+                        ..Default::default()
+                    },
+                    perf: PerfStats::default(),
+                })
+                .collect(),
+        };
+        assert!(report.check(Metric::TotalNum, Complexity::Constant).is_ok());
+        assert!(report.check(Metric::Peak, Complexity::Quadratic).is_ok());
+        let err = report.check(Metric::Peak, Complexity::Linear).unwrap_err();
+        assert!(matches!(
+            err,
+            ComplexityError::TooFast {
+                actual: Complexity::Quadratic,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn undetermined_complexity() {
+        assert_eq!(fit(&points(|n| 8.0 * n)[..2]), None);
+        // jumping between two levels from one size to the next
+        let noisy = points(|n| {
+            n * n
+                * if (n.log2() as u64).is_multiple_of(2) {
+                    1.0
+                } else {
+                    0.05
+                }
+        });
+        assert_eq!(fit(&noisy), None);
+
+        let report = ComplexityReport {
+            points: noisy
+                .iter()
+                .map(|&(n, peak)| SizePoint {
+                    n: n as usize,
+                    alloc: MemoryStats {
+                        peak: peak as usize,
+                        ..Default::default()
+                    },
+                    perf: PerfStats::default(),
+                })
+                .collect(),
+        };
+        let err = report
+            .check(Metric::Peak, Complexity::Quadratic)
+            .unwrap_err();
+        assert!(matches!(err, ComplexityError::Undetermined { .. }));
+    }
+}
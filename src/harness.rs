@@ -0,0 +1,230 @@
+//! Benchmark runner for `harness = false` targets.
+//!
+//! The allocation statistics are process-global, so each benchmark is run in
+//! its own child process. Functions annotated with
+//! [`#[alloc_test]`](crate::alloc_test) or [`#[perf_test]`](crate::perf_test)
+//! are registered automatically, other benchmark functions are passed to
+//! [`main!`](crate::main):
+//!
+//! ```ignore
+//! #[alloc_test(peak = "10%")]
+//! fn parse_config() -> Config {
+//!     Config::parse(INPUT)
+//! }
+//!
+//! fn sort() {
+//!     perf_bench!(sort_large, &perf_thresholds()).unwrap();
+//! }
+//!
+//! alloc_test::main!(sort);
+//! ```
+//!
+//! The runner accepts `cargo test`-style name filters and `--exact`, as well
+//! as `--list`. Arguments after `--` are passed to every benchmark, e.g.
+//! `cargo bench -- parse -- --save-baseline target/baseline`.
+
+use std::{
+    env,
+    process::{Command, ExitCode},
+};
+
+/// Environment variable telling a child process which benchmark to run.
+const CHILD_ENV: &str = "ALLOC_TEST_HARNESS_BENCH";
+
+/// A registered benchmark. Fails by panicking, like a test.
+#[derive(Debug, Clone, Copy)]
+pub struct Benchmark {
+    /// Path of the benchmark function, including the crate name, matched
+    /// against the name filters.
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+#[doc(hidden)]
+pub use inventory;
+
+inventory::collect!(Benchmark);
+
+/// Benchmarks registered by the attribute macros.
+pub fn registered() -> impl Iterator<Item = Benchmark> {
+    inventory::iter::<Benchmark>.into_iter().copied()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct HarnessArgs {
+    filters: Vec<String>,
+    exact: bool,
+    list: bool,
+    passthrough: Vec<String>,
+}
+
+impl HarnessArgs {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut parsed = HarnessArgs::default();
+        let mut args = args.into_iter();
+        for arg in args.by_ref() {
+            match arg.as_str() {
+                "--" => break,
+                "--exact" => parsed.exact = true,
+                "--list" => parsed.list = true,
+                // passed by `cargo bench`
+                "--bench" => {}
+                _ if arg.starts_with('-') => panic!("unknown harness option `{arg}`"),
+                _ => parsed.filters.push(arg),
+            }
+        }
+        parsed.passthrough = args.filter(|a| a != "--bench").collect();
+        parsed
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        self.filters.is_empty()
+            || self.filters.iter().any(|f| match self.exact {
+                true => name == f,
+                false => name.contains(f.as_str()),
+            })
+    }
+}
+
+/// Runs the benchmarks selected by the command line, each in a child process,
+/// or, inside such a child process, the benchmark it was started for.
+pub fn run(benchmarks: &[Benchmark]) -> ExitCode {
+    if let Ok(name) = env::var(CHILD_ENV) {
+        return match benchmarks.iter().find(|b| b.name == name) {
+            Some(bench) => {
+                (bench.run)();
+                ExitCode::SUCCESS
+            }
+            None => panic!("no benchmark named `{name}`"),
+        };
+    }
+
+    let args = HarnessArgs::parse(env::args().skip(1));
+    let selected = benchmarks
+        .iter()
+        .filter(|b| args.selects(b.name))
+        .collect::<Vec<_>>();
+    if args.list {
+        for bench in &selected {
+            log!("{}: benchmark", bench.name);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let exe = env::current_exe().expect("cannot locate the benchmark executable");
+    log!("\nrunning {} benchmarks", selected.len());
+    let mut failed = Vec::new();
+    for bench in &selected {
+        let status = Command::new(&exe)
+            .env(CHILD_ENV, bench.name)
+            // the arguments expected by `check_threshold_with_args`
+            .args([bench.name, "--exact", "--"])
+            .args(&args.passthrough)
+            .status();
+        match status {
+            Ok(status) if status.success() => log!("bench {} ... ok", bench.name),
+            Ok(status) => {
+                log!("bench {} ... FAILED ({status})", bench.name);
+                failed.push(bench.name);
+            }
+            Err(e) => {
+                log!("bench {} ... FAILED (cannot spawn: {e})", bench.name);
+                failed.push(bench.name);
+            }
+        }
+    }
+
+    let result = if failed.is_empty() { "ok" } else { "FAILED" };
+    log!(
+        "\nbenchmark result: {result}. {} passed; {} failed; {} filtered out",
+        selected.len() - failed.len(),
+        failed.len(),
+        benchmarks.len() - selected.len()
+    );
+    for name in &failed {
+        log!("    {name}");
+    }
+    if failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Runs a benchmark from a `#[test]` function named `name`, given by its path
+/// including the crate name, e.g. `concat!(module_path!(), "::", "parse")`.
+///
+/// Allocations of all threads are traced, so `run` is called in a child
+/// process of the test executable running only this test, on a single thread.
+/// The output of the child is printed, to be captured by the test harness.
+pub fn run_test(name: &str, run: fn()) {
+    if env::var_os(CHILD_ENV).is_some() {
+        return run();
+    }
+    // test names don't include the crate name
+    let test = name.split_once("::").map_or(name, |(_, test)| test);
+    let exe = env::current_exe().expect("cannot locate the test executable");
+    let output = Command::new(exe)
+        .env(CHILD_ENV, name)
+        .args([test, "--exact", "--include-ignored", "--nocapture"])
+        .arg("--test-threads=1")
+        .output()
+        .unwrap_or_else(|e| panic!("cannot spawn the test executable: {e}"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    print!("{stdout}");
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "benchmark `{name}` failed");
+    assert!(
+        stdout.contains(" 1 passed;"),
+        "test `{test}` was not run by the child process"
+    );
+}
+
+/// Defines `main` for a `harness = false` target running the
+/// [registered](registered) benchmarks and the given benchmark functions. See
+/// [`harness`](crate::harness).
+#[macro_export]
+macro_rules! main {
+    ($($bench:ident),* $(,)?) => {
+        fn main() -> ::std::process::ExitCode {
+            let mut benchmarks = vec![$($crate::harness::Benchmark {
+                name: concat!(module_path!(), "::", stringify!($bench)),
+                run: $bench,
+            }),*];
+            benchmarks.extend($crate::harness::registered());
+            $crate::harness::run(&benchmarks)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> HarnessArgs {
+        HarnessArgs::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn harness_args() {
+        let args = parse(&["parse", "--exact", "--", "-s", "dir", "--bench"]);
+        assert_eq!(
+            args,
+            HarnessArgs {
+                filters: vec!["parse".into()],
+                exact: true,
+                list: false,
+                passthrough: vec!["-s".into(), "dir".into()],
+            }
+        );
+        assert!(args.selects("parse"));
+        assert!(!args.selects("parse_config"));
+
+        let args = parse(&["--bench", "parse", "sort"]);
+        assert!(args.selects("parse_config"));
+        assert!(args.selects("sort_large"));
+        assert!(!args.selects("clone"));
+
+        assert!(parse(&[]).selects("clone"));
+    }
+}
@@ -15,6 +15,14 @@ macro_rules! log {
     };
 }
 
+extern crate self as alloc_test;
+
+#[cfg(feature = "benchmark")]
+pub use alloc_test_macros::{alloc_test, perf_test};
+
 pub mod alloc;
+pub mod bench;
+pub mod complexity;
+pub mod harness;
 pub mod perf;
 pub mod threshold;
@@ -1,4 +1,4 @@
-use super::measure::PerfStats;
+use super::measure::{PerfBenchOptions, PerfStats};
 
 pub fn perf_benchmark<F: Fn() -> O, O>(id: &str, f: F) -> PerfStats {
     let stats = super::measure::bench(f);
@@ -6,38 +6,206 @@ pub fn perf_benchmark<F: Fn() -> O, O>(id: &str, f: F) -> PerfStats {
     stats
 }
 
+pub fn perf_benchmark_with<F: Fn() -> O, O>(
+    id: &str,
+    options: &PerfBenchOptions,
+    f: F,
+) -> PerfStats {
+    let stats = super::measure::bench_with(options, f);
+    log!("\nperformance stats for `{id}`:\n{stats}");
+    stats
+}
+
+/// Like [`perf_benchmark_with`], but every run of `f` is given a fresh input
+/// built by `setup`, outside of the timed region.
+pub fn perf_benchmark_batched<I, O, S, F>(
+    id: &str,
+    options: &PerfBenchOptions,
+    setup: S,
+    f: F,
+) -> PerfStats
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+{
+    let stats = super::measure::bench_batched(options, setup, f);
+    log!("\nperformance stats for `{id}`:\n{stats}");
+    stats
+}
+
+pub fn perf_benchmark_with_setup<I, O, S, F, T>(id: &str, setup: S, f: F, teardown: T) -> PerfStats
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+    T: Fn(O),
+{
+    let stats = super::measure::bench_with_setup(setup, f, teardown);
+    log!("\nperformance stats for `{id}`:\n{stats}");
+    stats
+}
+
 pub fn perf_log_toml<F: Fn() -> O, O>(id: &str, f: F) -> PerfStats {
     let stats = super::measure::bench(f);
-    log!("\nperformance stats for `{id}`:\n{stats}", stats = toml::to_string(&stats).unwrap());
+    log!(
+        "\nperformance stats for `{id}`:\n{stats}",
+        stats = toml::to_string(&stats).unwrap()
+    );
     stats
 }
 
+/// Checks performance of a benchmark against its baseline.
+///
+/// Accepts the same forms as [`alloc_bench!`](crate::alloc_bench): either a
+/// function name, used as the baseline id, or an explicit id followed by any
+/// callable expression.
 #[macro_export]
 macro_rules! perf_bench {
     ($test:ident, $thresh:expr) => {
+        $crate::perf_bench!(stringify!($test), $test, $thresh)
+    };
+    ($id:expr, $f:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let f = $f;
         $crate::threshold::check_threshold_with_args(
-            || $crate::perf::benchmark::perf_benchmark(stringify!($test), $test),
+            || $crate::perf::benchmark::perf_benchmark(id, &f),
             "perf_bench",
-            stringify!($test),
+            id,
             $thresh,
         )
+    }};
+}
+
+/// Like [`perf_bench!`], with the benchmark configured by
+/// [`PerfBenchOptions`].
+///
+/// Accepts either `(test, options, thresholds)` or
+/// `(id, f, options, thresholds)`.
+#[macro_export]
+macro_rules! perf_bench_with {
+    ($test:ident, $options:expr, $thresh:expr) => {
+        $crate::perf_bench_with!(stringify!($test), $test, $options, $thresh)
     };
+    ($id:expr, $f:expr, $options:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let (f, options) = ($f, $options);
+        $crate::threshold::check_threshold_with_args(
+            || $crate::perf::benchmark::perf_benchmark_with(id, &options, &f),
+            "perf_bench",
+            id,
+            $thresh,
+        )
+    }};
 }
 
+/// Like [`perf_bench!`], but the input of the benchmarked function is built
+/// by `setup` and its output is consumed by `teardown` on every iteration,
+/// outside of the timed region.
+///
+/// Accepts either `(test, setup, teardown, thresholds)` or
+/// `(id, f, setup, teardown, thresholds)`.
+#[macro_export]
+macro_rules! perf_bench_with_setup {
+    ($test:ident, $setup:expr, $teardown:expr, $thresh:expr) => {
+        $crate::perf_bench_with_setup!(stringify!($test), $test, $setup, $teardown, $thresh)
+    };
+    ($id:expr, $f:expr, $setup:expr, $teardown:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let (f, setup, teardown) = ($f, $setup, $teardown);
+        $crate::threshold::check_threshold_with_args(
+            || $crate::perf::benchmark::perf_benchmark_with_setup(id, &setup, &f, &teardown),
+            "perf_bench",
+            id,
+            $thresh,
+        )
+    }};
+}
+
+/// Like [`perf_bench_with!`], but every run of the benchmarked function is
+/// given a fresh input built by `setup`, outside of the timed region. Unlike
+/// [`perf_bench_with_setup!`], runs are batched, so this suits functions that
+/// consume or mutate a small input.
+///
+/// Accepts either `(test, setup, options, thresholds)` or
+/// `(id, f, setup, options, thresholds)`.
+#[macro_export]
+macro_rules! perf_bench_batched {
+    ($test:ident, $setup:expr, $options:expr, $thresh:expr) => {
+        $crate::perf_bench_batched!(stringify!($test), $test, $setup, $options, $thresh)
+    };
+    ($id:expr, $f:expr, $setup:expr, $options:expr, $thresh:expr) => {{
+        let id: &str = &$id;
+        let (f, setup, options) = ($f, $setup, $options);
+        $crate::threshold::check_threshold_with_args(
+            || $crate::perf::benchmark::perf_benchmark_batched(id, &options, &setup, &f),
+            "perf_bench",
+            id,
+            $thresh,
+        )
+    }};
+}
+
+/// Like [`perf_bench!`], but compares with a baseline given as a TOML string.
+/// Without a baseline, only logs the stats in TOML format.
 #[macro_export]
 macro_rules! perf_bench_cmp_with_toml {
-    ($test:ident $(,)?) => {{
-        let value = $crate::perf::benchmark::perf_log_toml(stringify!($test), $test);
+    ($test:ident $(,)?) => {
+        $crate::perf_bench_cmp_with_toml!(stringify!($test), $test)
+    };
+    ($test:ident, $toml:expr, $limits:expr $(,)?) => {
+        $crate::perf_bench_cmp_with_toml!(stringify!($test), $test, $toml, $limits)
+    };
+    ($id:expr, $f:expr $(,)?) => {{
+        let value = $crate::perf::benchmark::perf_log_toml(&$id, $f);
         Result::<
             $crate::perf::measure::PerfStats,
             $crate::threshold::CheckThresholdError<$crate::perf::compare::PerfThresholdsError>,
         >::Ok(value)
     }};
-    ($test:ident, $toml:expr, $limits:expr $(,)?) => {{
+    ($id:expr, $f:expr, $toml:expr, $limits:expr $(,)?) => {{
+        let id: &str = &$id;
+        let f = $f;
         $crate::threshold::check_threshold_with_str(
-            || $crate::perf::benchmark::perf_benchmark(stringify!($test), $test),
+            || $crate::perf::benchmark::perf_benchmark(id, &f),
             $toml,
             $limits,
         )
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{perf::compare::PerfThresholdsBuilder, threshold::Threshold};
+
+    fn sum(n: u64) -> u64 {
+        (0..n).sum()
+    }
+
+    #[test]
+    fn explicit_ids() {
+        let thresholds = PerfThresholdsBuilder::default()
+            .mean_ns(Threshold::cap(1_000_000))
+            .build()
+            .unwrap();
+        for n in [10, 100] {
+            let id = format!("sum_{n}");
+            assert!(crate::perf_bench_cmp_with_toml!(id, || sum(n)).is_ok());
+            assert!(
+                crate::perf_bench_cmp_with_toml!(id, || sum(n), "mean_ns = 0", &thresholds).is_ok()
+            );
+        }
+    }
+
+    #[test]
+    fn setup_teardown() {
+        let stats = super::perf_benchmark_with_setup(
+            "sort",
+            || (0..1000).rev().collect::<Vec<u32>>(),
+            |mut v| {
+                v.sort();
+                v
+            },
+            |v| assert!(v.windows(2).all(|w| w[0] <= w[1])),
+        );
+        assert!(stats.mean_ns < 1_000_000);
+    }
+}
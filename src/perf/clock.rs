@@ -0,0 +1,109 @@
+//! Clocks timing the benchmarked code.
+
+use std::time::Duration;
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use super::measure::Instant;
+
+/// Clock measuring the duration of each sample.
+///
+/// CPU time clocks ignore the time the thread spends descheduled, which
+/// makes them much less sensitive to other load on the machine. They are only
+/// available on Linux, elsewhere [`Clock::Wall`] is used instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clock {
+    #[default]
+    #[display(fmt = "wall")]
+    Wall,
+    /// CPU time of the calling thread.
+    #[display(fmt = "thread CPU")]
+    ThreadCpu,
+    /// CPU time of all threads of the process.
+    #[display(fmt = "process CPU")]
+    ProcessCpu,
+}
+
+impl Clock {
+    /// The clock actually used when this one is requested.
+    pub fn resolve(self) -> Clock {
+        match self {
+            #[cfg(not(target_os = "linux"))]
+            Clock::ThreadCpu | Clock::ProcessCpu => Clock::Wall,
+            clock => clock,
+        }
+    }
+
+    /// Starts measuring time on the [resolved](Clock::resolve) clock.
+    pub fn start(self) -> Stopwatch {
+        match self.resolve() {
+            #[cfg(target_os = "linux")]
+            Clock::ThreadCpu => Stopwatch::Cpu(
+                libc::CLOCK_THREAD_CPUTIME_ID,
+                cpu_time(libc::CLOCK_THREAD_CPUTIME_ID),
+            ),
+            #[cfg(target_os = "linux")]
+            Clock::ProcessCpu => Stopwatch::Cpu(
+                libc::CLOCK_PROCESS_CPUTIME_ID,
+                cpu_time(libc::CLOCK_PROCESS_CPUTIME_ID),
+            ),
+            _ => Stopwatch::Wall(Instant::now()),
+        }
+    }
+}
+
+/// Time measurement started by [`Clock::start`].
+#[derive(Debug, Clone, Copy)]
+pub enum Stopwatch {
+    Wall(Instant),
+    #[cfg(target_os = "linux")]
+    Cpu(libc::clockid_t, Duration),
+}
+
+impl Stopwatch {
+    pub fn elapsed(&self) -> Duration {
+        match *self {
+            Stopwatch::Wall(start) => start.elapsed(),
+            #[cfg(target_os = "linux")]
+            Stopwatch::Cpu(clock, start) => cpu_time(clock).saturating_sub(start),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_time(clock: libc::clockid_t) -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let result = unsafe { libc::clock_gettime(clock, &mut time) };
+    assert_eq!(result, 0, "clock_gettime failed");
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_clocks() {
+        let spin = |clock: Clock| {
+            let stopwatch = clock.start();
+            let wall = Instant::now();
+            while wall.elapsed() < Duration::from_millis(5) {}
+            stopwatch.elapsed()
+        };
+        assert!(spin(Clock::ThreadCpu) > Duration::from_millis(1));
+        assert!(spin(Clock::ProcessCpu) > Duration::from_millis(1));
+
+        let stopwatch = Clock::ThreadCpu.start();
+        std::thread::sleep(Duration::from_millis(20));
+        let slept = stopwatch.elapsed();
+        #[cfg(target_os = "linux")]
+        assert!(slept < Duration::from_millis(10));
+        #[cfg(not(target_os = "linux"))]
+        assert!(slept >= Duration::from_millis(20));
+    }
+}
@@ -3,29 +3,270 @@ use thiserror::Error;
 
 use crate::threshold::{Threshold, ThresholdError, ThresholdFor};
 
-use super::measure::PerfStats;
+use super::{
+    clock::Clock,
+    measure::PerfStats,
+    significance::{welch_t_test, Significance},
+};
 
-#[derive(Debug, Builder)]
+/// Limits for each performance statistics parameter.
+#[derive(Debug, Clone, Builder)]
 pub struct PerfThresholds {
-    pub mean: Threshold<u64>,
+    #[builder(default)]
+    pub mean_ns: Threshold<u64>,
+    #[builder(default)]
+    pub stdev_ns: Threshold<u64>,
+    #[builder(default)]
+    pub median_ns: Threshold<u64>,
+    #[builder(default)]
+    pub p90_ns: Threshold<u64>,
+    #[builder(default)]
+    pub p95_ns: Threshold<u64>,
+    #[builder(default)]
+    pub p99_ns: Threshold<u64>,
+    #[builder(default)]
+    pub max_ns: Threshold<u64>,
+    /// Significance level. When set and both the stats and the baseline
+    /// summarize at least two samples in [`PerfStats::moments`], a `mean_ns`
+    /// exceeding its threshold is only a regression if Welch's t-test finds
+    /// the change significant at this level.
+    #[builder(default)]
+    pub significance: Option<f64>,
+    /// Limits for hardware counts per run, checked only when both the stats
+    /// and the baseline carry them.
+    #[builder(default)]
+    pub instructions: Threshold<u64>,
+    #[builder(default)]
+    pub cycles: Threshold<u64>,
+    #[builder(default)]
+    pub branch_misses: Threshold<u64>,
+    #[builder(default)]
+    pub cache_misses: Threshold<u64>,
+    /// Limits for decreases of throughput, checked only when both the stats
+    /// and the baseline carry it.
+    #[builder(default)]
+    pub bytes_per_sec: Threshold<u64>,
+    #[builder(default)]
+    pub elements_per_sec: Threshold<u64>,
 }
 
 #[derive(Debug, Error)]
-#[error("Performance parameter `{param}`: {error}")]
-pub struct PerfThresholdsError {
-    error: ThresholdError<u64>,
-    param: &'static str,
+pub enum PerfThresholdsError {
+    #[error("Performance parameter `{param}`: {error}{}", significance.map(|s| format!(", {s}")).unwrap_or_default())]
+    Regression {
+        error: ThresholdError<u64>,
+        param: &'static str,
+        significance: Option<Significance>,
+    },
+    /// Durations measured on different clocks are not comparable.
+    #[error("stats measured on the {value} clock cannot be compared with a baseline measured on the {baseline} clock")]
+    ClockMismatch { value: Clock, baseline: Clock },
+}
+
+macro_rules! check {
+    ($f:ident, $l:expr, $v:expr, $r:expr) => {
+        $l.$f
+            .check(&$v.$f, &$r.$f)
+            .map_err(|e| PerfThresholdsError::Regression {
+                error: e,
+                param: stringify!($f),
+                significance: None,
+            })
+    };
+}
+
+macro_rules! check_decrease {
+    ($f:ident, $l:expr, $v:expr, $r:expr) => {
+        match ($v.$f, $r.$f) {
+            (Some(value), Some(ref_value)) => {
+                $l.$f.check_decrease(&value, &ref_value).map_err(|e| {
+                    PerfThresholdsError::Regression {
+                        error: e,
+                        param: stringify!($f),
+                        significance: None,
+                    }
+                })
+            }
+            _ => Ok(()),
+        }
+    };
+}
+
+impl PerfThresholds {
+    fn check_mean(
+        &self,
+        value: &PerfStats,
+        ref_value: &PerfStats,
+    ) -> Result<(), PerfThresholdsError> {
+        let Some(alpha) = self.significance else {
+            return check!(mean_ns, self, value, ref_value);
+        };
+        let Some(significance) = welch_t_test(&value.moments, &ref_value.moments) else {
+            return check!(mean_ns, self, value, ref_value);
+        };
+        log!("mean {significance}");
+        match check!(mean_ns, self, value, ref_value) {
+            Err(PerfThresholdsError::Regression { error, param, .. })
+                if significance.is_significant(alpha) =>
+            {
+                Err(PerfThresholdsError::Regression {
+                    error,
+                    param,
+                    significance: Some(significance),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl ThresholdFor<PerfStats> for PerfThresholds {
     type Error = PerfThresholdsError;
 
     fn check_threshold(&self, value: &PerfStats, ref_value: &PerfStats) -> Result<(), Self::Error> {
-        self.mean
-            .check(&value.mean, &ref_value.mean)
-            .map_err(|error| PerfThresholdsError {
-                error,
-                param: "mean",
-            })
+        if value.clock != ref_value.clock {
+            return Err(PerfThresholdsError::ClockMismatch {
+                value: value.clock,
+                baseline: ref_value.clock,
+            });
+        }
+        self.check_mean(value, ref_value)?;
+        check!(stdev_ns, self, value, ref_value)?;
+        check!(median_ns, self, value, ref_value)?;
+        check!(p90_ns, self, value, ref_value)?;
+        check!(p95_ns, self, value, ref_value)?;
+        check!(p99_ns, self, value, ref_value)?;
+        check!(max_ns, self, value, ref_value)?;
+        if let (Some(value), Some(ref_value)) =
+            (value.counters.counted(), ref_value.counters.counted())
+        {
+            check!(instructions, self, value, ref_value)?;
+            check!(cycles, self, value, ref_value)?;
+            check!(branch_misses, self, value, ref_value)?;
+            check!(cache_misses, self, value, ref_value)?;
+        }
+        check_decrease!(bytes_per_sec, self, value, ref_value)?;
+        check_decrease!(elements_per_sec, self, value, ref_value)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perf::{
+        counters::{Counters, HardwareCounters},
+        significance::Moments,
+    };
+
+    fn stats(samples: &[u64]) -> PerfStats {
+        PerfStats {
+            mean_ns: samples.iter().sum::<u64>() / samples.len() as u64,
+            moments: Moments::of(&samples.iter().map(|&s| s as f64).collect::<Vec<_>>()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn significance() {
+        let base = stats(&[100, 100, 100, 100, 100, 100, 100, 100, 100, 130]);
+        let noisy = stats(&[100, 100, 100, 100, 100, 100, 100, 100, 100, 200]);
+        let slower = stats(&[120, 121, 119, 120, 120, 121, 119, 120, 120, 120]);
+        let thresholds = PerfThresholdsBuilder::default()
+            .mean_ns(Threshold::ratio(5, 100))
+            .significance(Some(0.05))
+            .build()
+            .unwrap();
+
+        // mean is 7% higher, but because of a single sample
+        assert!(thresholds.check_threshold(&noisy, &base).is_ok());
+        let error = thresholds.check_threshold(&slower, &base).unwrap_err();
+        assert!(matches!(
+            error,
+            PerfThresholdsError::Regression {
+                significance: Some(s),
+                ..
+            } if s.p_value < 0.05
+        ));
+
+        let thresholds = PerfThresholds {
+            significance: None,
+            ..thresholds
+        };
+        assert!(thresholds.check_threshold(&noisy, &base).is_err());
+    }
+
+    #[test]
+    fn counters() {
+        let with_instructions = |instructions| PerfStats {
+            counters: Counters::Counted(HardwareCounters {
+                instructions,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let thresholds = PerfThresholdsBuilder::default()
+            .instructions(Threshold::ratio(1, 100))
+            .build()
+            .unwrap();
+        let base = with_instructions(1000);
+        assert!(thresholds
+            .check_threshold(&with_instructions(1010), &base)
+            .is_ok());
+        assert!(thresholds
+            .check_threshold(&with_instructions(1011), &base)
+            .is_err());
+        // counters were unavailable for the new stats
+        let unavailable = PerfStats {
+            counters: Counters::Unavailable,
+            ..Default::default()
+        };
+        assert!(thresholds.check_threshold(&unavailable, &base).is_ok());
+    }
+
+    #[test]
+    fn throughput() {
+        let with_rate = |rate| PerfStats {
+            bytes_per_sec: Some(rate),
+            ..Default::default()
+        };
+        let thresholds = PerfThresholdsBuilder::default()
+            .bytes_per_sec(Threshold::ratio(5, 100))
+            .build()
+            .unwrap();
+        let base = with_rate(1000);
+        assert!(thresholds.check_threshold(&with_rate(950), &base).is_ok());
+        let error = thresholds
+            .check_threshold(&with_rate(949), &base)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            PerfThresholdsError::Regression {
+                param: "bytes_per_sec",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn clock_mismatch() {
+        let thresholds = PerfThresholdsBuilder::default().build().unwrap();
+        let on = |clock| PerfStats {
+            clock,
+            ..Default::default()
+        };
+        assert!(thresholds
+            .check_threshold(&on(Clock::ThreadCpu), &on(Clock::ThreadCpu))
+            .is_ok());
+        let error = thresholds
+            .check_threshold(&on(Clock::ThreadCpu), &on(Clock::Wall))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            PerfThresholdsError::ClockMismatch {
+                value: Clock::ThreadCpu,
+                baseline: Clock::Wall,
+            }
+        ));
     }
 }
@@ -0,0 +1,275 @@
+//! Hardware performance counters, read through `perf_event_open` on Linux.
+
+#[cfg(target_os = "linux")]
+use std::io;
+use std::ops::{Add, Div};
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Hardware events counted during one run of the benchmarked function.
+///
+/// Only events in user space are counted, which keeps the counts nearly
+/// deterministic.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[display(
+    fmt = "instructions = {instructions}, cycles = {cycles}, branch misses = {branch_misses}, cache misses = {cache_misses}"
+)]
+pub struct HardwareCounters {
+    #[serde(default)]
+    pub instructions: u64,
+    #[serde(default)]
+    pub cycles: u64,
+    #[serde(default)]
+    pub branch_misses: u64,
+    #[serde(default)]
+    pub cache_misses: u64,
+}
+
+impl Add for HardwareCounters {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        HardwareCounters {
+            instructions: self.instructions + other.instructions,
+            cycles: self.cycles + other.cycles,
+            branch_misses: self.branch_misses + other.branch_misses,
+            cache_misses: self.cache_misses + other.cache_misses,
+        }
+    }
+}
+
+impl Div<u64> for HardwareCounters {
+    type Output = Self;
+
+    fn div(self, n: u64) -> Self {
+        HardwareCounters {
+            instructions: self.instructions / n,
+            cycles: self.cycles / n,
+            branch_misses: self.branch_misses / n,
+            cache_misses: self.cache_misses / n,
+        }
+    }
+}
+
+/// Hardware counts of a benchmark, telling apart counters that were not
+/// requested from counters that could not be read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Counters {
+    #[default]
+    NotRequested,
+    /// Requested, but the kernel or the hardware doesn't allow counting.
+    Unavailable,
+    /// Mean counts per run.
+    Counted(HardwareCounters),
+}
+
+impl Counters {
+    pub fn counted(&self) -> Option<&HardwareCounters> {
+        match self {
+            Counters::Counted(counters) => Some(counters),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CountersUnavailable {
+    #[error("hardware counters are only supported on Linux")]
+    Unsupported,
+    #[cfg(target_os = "linux")]
+    #[error(
+        "cannot open the `{event}` counter: {error} (see `/proc/sys/kernel/perf_event_paranoid`)"
+    )]
+    Open {
+        event: &'static str,
+        error: io::Error,
+    },
+    #[cfg(target_os = "linux")]
+    #[error("cannot read hardware counters: {_0}")]
+    Read(io::Error),
+    /// The kernel never scheduled the counters on the hardware, e.g. because
+    /// other counters occupy it or a virtual machine doesn't expose it.
+    #[error("hardware counters were never scheduled")]
+    NotScheduled,
+}
+
+/// Group of hardware counters of the calling thread, scheduled together so
+/// that their counts cover the same instructions.
+#[derive(Debug)]
+pub struct CounterGroup {
+    #[cfg(target_os = "linux")]
+    fds: Vec<libc::c_int>,
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    /// Prefix of `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER0`.
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct PerfEventAttr {
+        pub kind: u32,
+        pub size: u32,
+        pub config: u64,
+        pub sample_period: u64,
+        pub sample_type: u64,
+        pub read_format: u64,
+        pub flags: u64,
+        pub wakeup_events: u32,
+        pub bp_type: u32,
+        pub config1: u64,
+    }
+
+    pub const PERF_TYPE_HARDWARE: u32 = 0;
+    pub const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    pub const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+    pub const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+    pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+    pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+    pub const PERF_FORMAT_GROUP: u64 = 1 << 3;
+    pub const FLAG_DISABLED: u64 = 1 << 0;
+    pub const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    pub const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+    pub const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+    pub const PERF_EVENT_IOC_ENABLE: u64 = 0x2400;
+    pub const PERF_EVENT_IOC_DISABLE: u64 = 0x2401;
+    pub const PERF_EVENT_IOC_RESET: u64 = 0x2403;
+    pub const PERF_IOC_FLAG_GROUP: libc::c_ulong = 1;
+}
+
+#[cfg(target_os = "linux")]
+const EVENTS: [(&str, u64); 4] = [
+    ("instructions", sys::PERF_COUNT_HW_INSTRUCTIONS),
+    ("cycles", sys::PERF_COUNT_HW_CPU_CYCLES),
+    ("branch-misses", sys::PERF_COUNT_HW_BRANCH_MISSES),
+    ("cache-misses", sys::PERF_COUNT_HW_CACHE_MISSES),
+];
+
+impl CounterGroup {
+    /// Opens the counters, failing if the kernel or the hardware doesn't
+    /// allow counting any of them.
+    #[cfg(target_os = "linux")]
+    pub fn open() -> Result<Self, CountersUnavailable> {
+        let mut group = CounterGroup { fds: Vec::new() };
+        for (event, config) in EVENTS {
+            let leader = group.fds.first().copied();
+            let attr = sys::PerfEventAttr {
+                kind: sys::PERF_TYPE_HARDWARE,
+                size: std::mem::size_of::<sys::PerfEventAttr>() as u32,
+                config,
+                read_format: sys::PERF_FORMAT_GROUP
+                    | sys::PERF_FORMAT_TOTAL_TIME_ENABLED
+                    | sys::PERF_FORMAT_TOTAL_TIME_RUNNING,
+                flags: sys::FLAG_EXCLUDE_KERNEL
+                    | sys::FLAG_EXCLUDE_HV
+                    | if leader.is_none() {
+                        sys::FLAG_DISABLED
+                    } else {
+                        0
+                    },
+                ..Default::default()
+            };
+            // this thread, on any CPU
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const sys::PerfEventAttr,
+                    0,
+                    -1,
+                    leader.unwrap_or(-1),
+                    sys::PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                let error = io::Error::last_os_error();
+                return Err(CountersUnavailable::Open { event, error });
+            }
+            group.fds.push(fd as libc::c_int);
+        }
+        Ok(group)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open() -> Result<Self, CountersUnavailable> {
+        Err(CountersUnavailable::Unsupported)
+    }
+
+    /// Counts the events occurring while `f` runs. The enabling and reading
+    /// of the counters happen outside of `f`, so `f` can time itself without
+    /// their syscalls.
+    ///
+    /// If the kernel multiplexed the counters with others, the counts are
+    /// scaled up to the whole run.
+    #[cfg(target_os = "linux")]
+    pub fn measure<O, F: FnOnce() -> O>(
+        &self,
+        f: F,
+    ) -> (O, Result<HardwareCounters, CountersUnavailable>) {
+        self.ioctl(sys::PERF_EVENT_IOC_RESET);
+        self.ioctl(sys::PERF_EVENT_IOC_ENABLE);
+        let output = f();
+        self.ioctl(sys::PERF_EVENT_IOC_DISABLE);
+
+        // number of events, time enabled, time running, then the counts
+        let mut values = [0_u64; 3 + EVENTS.len()];
+        let size = std::mem::size_of_val(&values);
+        let read = unsafe { libc::read(self.fds[0], values.as_mut_ptr().cast(), size) };
+        if read != size as isize {
+            let error = io::Error::last_os_error();
+            return (output, Err(CountersUnavailable::Read(error)));
+        }
+        let [_, enabled, running, instructions, cycles, branch_misses, cache_misses] = values;
+        if running == 0 {
+            return (output, Err(CountersUnavailable::NotScheduled));
+        }
+        let scale = |count: u64| (count as u128 * enabled as u128 / running as u128) as u64;
+        let counters = HardwareCounters {
+            instructions: scale(instructions),
+            cycles: scale(cycles),
+            branch_misses: scale(branch_misses),
+            cache_misses: scale(cache_misses),
+        };
+        (output, Ok(counters))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn measure<O, F: FnOnce() -> O>(
+        &self,
+        f: F,
+    ) -> (O, Result<HardwareCounters, CountersUnavailable>) {
+        (f(), Err(CountersUnavailable::Unsupported))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn ioctl(&self, request: u64) {
+        unsafe { libc::ioctl(self.fds[0], request as _, sys::PERF_IOC_FLAG_GROUP) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for CounterGroup {
+    fn drop(&mut self) {
+        for &fd in &self.fds {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_or_unavailable() {
+        let group = match CounterGroup::open() {
+            Ok(group) => group,
+            Err(e) => return println!("{e}"),
+        };
+        let (sum, counters) = group.measure(|| (0..std::hint::black_box(10_000_u64)).sum::<u64>());
+        assert_eq!(sum, 49_995_000);
+        assert!(counters.unwrap().instructions > 10_000);
+    }
+}
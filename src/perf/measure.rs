@@ -75,83 +75,751 @@ export function performance_now() {
     }
 }
 
-#[derive(Debug, Default)]
-struct Stats {
-    n: f64,
-    mean: f64,
-    q: f64,
+/// Distribution of the measured durations, in nanoseconds.
+///
+/// Baselines written before the switch from microseconds lack the `_ns`
+/// fields and fail to load instead of being compared in the wrong unit.
+#[derive(Debug, Default, Clone, Display, Serialize, Deserialize)]
+#[display(
+    fmt = "mean = {mean_ns}ns, stdev = {stdev_ns}ns, min = {min_ns}ns, median = {median_ns}ns, p90 = {p90_ns}ns, p95 = {p95_ns}ns, p99 = {p99_ns}ns, max = {max_ns}ns (n = {n}), drop = {drop_ns}ns, {clock} clock{}{}, {outliers}",
+    "format_throughput(bytes_per_sec, elements_per_sec)",
+    "format_counters(counters)"
+)]
+pub struct PerfStats {
+    pub mean_ns: u64,
+    /// Number of samples.
+    #[serde(default)]
+    pub n: u64,
+    #[serde(default)]
+    pub stdev_ns: u64,
+    #[serde(default)]
+    pub min_ns: u64,
+    #[serde(default)]
+    pub max_ns: u64,
+    #[serde(default)]
+    pub median_ns: u64,
+    #[serde(default)]
+    pub p90_ns: u64,
+    #[serde(default)]
+    pub p95_ns: u64,
+    #[serde(default)]
+    pub p99_ns: u64,
+    /// Mean time to drop the output, measured separately from the other
+    /// parameters unless [`PerfBenchOptions::include_drop`] is set.
+    #[serde(default)]
+    pub drop_ns: u64,
+    /// Clock the durations were measured on.
+    #[serde(default)]
+    pub clock: Clock,
+    /// Throughput at the mean time, if [`PerfBenchOptions::throughput`] is
+    /// given in bytes.
+    #[serde(default)]
+    pub bytes_per_sec: Option<u64>,
+    /// Throughput at the mean time, if [`PerfBenchOptions::throughput`] is
+    /// given in elements.
+    #[serde(default)]
+    pub elements_per_sec: Option<u64>,
+    /// Summary of the samples in nanoseconds, at full precision, kept for
+    /// significance testing against this baseline.
+    #[serde(default)]
+    pub moments: Moments,
+    /// Mean hardware counts per run, if they were requested.
+    #[serde(default)]
+    pub counters: Counters,
+    #[serde(default)]
+    pub outliers: OutlierCounts,
 }
 
-#[derive(Debug, Default, Display, Serialize, Deserialize)]
-#[display(fmt = "mean = {mean}μs")]
-pub struct PerfStats {
-    pub mean: u64,
+fn format_throughput(bytes_per_sec: &Option<u64>, elements_per_sec: &Option<u64>) -> String {
+    match (bytes_per_sec, elements_per_sec) {
+        (Some(bytes), _) => format!(", {:.2} MiB/s", *bytes as f64 / (1 << 20) as f64),
+        (_, Some(elements)) => format!(", {elements} elements/s"),
+        _ => String::new(),
+    }
 }
 
-impl From<Stats> for PerfStats {
-    fn from(source: Stats) -> Self {
-        let mean = Duration::from_secs_f64(source.mean)
-            .as_micros()
-            .try_into()
-            .unwrap();
-        PerfStats { mean }
+fn format_counters(counters: &Counters) -> String {
+    match counters {
+        Counters::NotRequested => String::new(),
+        Counters::Unavailable => ", hardware counters unavailable".to_string(),
+        Counters::Counted(counters) => format!(", {counters}"),
     }
 }
 
-impl std::fmt::Display for Stats {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if f.alternate() {
-            todo!()
-        } else {
-            write!(f, "mean: {}, stdev: {}", self.mean, self.q / self.n)
+fn nanos(secs: f64) -> u64 {
+    Duration::from_secs_f64(secs).as_nanos().try_into().unwrap()
+}
+
+impl PerfStats {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return PerfStats::default();
+        }
+        let mut sorted = samples
+            .iter()
+            .map(Duration::as_secs_f64)
+            .collect::<Vec<_>>();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = match sorted.len() {
+            1 => 0.0,
+            _ => sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+        // nearest-rank percentile
+        let percentile = |p: f64| sorted[((p * n).ceil() as usize).clamp(1, sorted.len()) - 1];
+        PerfStats {
+            mean_ns: nanos(mean),
+            n: sorted.len() as u64,
+            stdev_ns: nanos(variance.sqrt()),
+            min_ns: nanos(sorted[0]),
+            max_ns: nanos(sorted[sorted.len() - 1]),
+            median_ns: nanos(percentile(0.5)),
+            p90_ns: nanos(percentile(0.9)),
+            p95_ns: nanos(percentile(0.95)),
+            p99_ns: nanos(percentile(0.99)),
+            drop_ns: 0,
+            clock: Clock::Wall,
+            bytes_per_sec: None,
+            elements_per_sec: None,
+            moments: Moments {
+                n: sorted.len() as u64,
+                mean: mean * 1e9,
+                variance: variance * 1e18,
+            },
+            counters: Counters::NotRequested,
+            outliers: OutlierCounts::default(),
         }
     }
 }
 
-impl Stats {
-    pub fn new() -> Self {
-        Self::default()
+const ITERS: (usize, usize) = (20, 5);
+
+/// How many samples are taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Runs a fixed number of iterations, discarding `warmup` of them at the
+    /// beginning and as many at the end.
+    Fixed { iters: usize, warmup: usize },
+    /// Keeps sampling until the 95% confidence interval of the mean is
+    /// narrower than `precision` times the mean, or until `time_budget` runs
+    /// out. The first tenth of the budget is spent warming up. Slow functions
+    /// may exhaust the budget with only a few samples, their number is
+    /// reported in [`PerfStats::n`].
+    Adaptive {
+        precision: f64,
+        time_budget: Duration,
+    },
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::Fixed {
+            iters: ITERS.0,
+            warmup: ITERS.1,
+        }
     }
+}
+
+/// Amount of work done by one run of a benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throughput {
+    Bytes(u64),
+    Elements(u64),
+}
+
+/// Options of [`bench_with`].
+#[derive(Debug, Clone, Default, Builder)]
+pub struct PerfBenchOptions {
+    #[builder(default)]
+    pub sampling: Sampling,
+    /// Outliers excluded from the statistics. They are counted regardless.
+    #[builder(default)]
+    pub outliers: OutlierFilter,
+    /// Number of runs timed together as one sample, the sample being their
+    /// average. By default, enough runs to last [`MIN_BATCH_TIME`], so that
+    /// functions much faster than the resolution of [`Instant`] can be
+    /// measured.
+    #[builder(default)]
+    pub batch: Option<usize>,
+    /// Whether dropping the output is timed together with computing it.
+    /// Either way, the drop time is reported in [`PerfStats::drop_ns`].
+    #[builder(default)]
+    pub include_drop: bool,
+    #[builder(default)]
+    pub clock: Clock,
+    /// Whether to read hardware counters with [`CounterGroup`]. If they are
+    /// unavailable, a warning is logged and [`PerfStats::counters`] is
+    /// [`Counters::Unavailable`].
+    #[builder(default)]
+    pub counters: bool,
+    /// Work done by each run, to report throughput next to time.
+    #[builder(default)]
+    pub throughput: Option<Throughput>,
+}
 
-    pub fn update(&mut self, dur: Duration) {
-        let x = dur.as_secs_f64();
+/// Minimal duration of a sample when the batch size is chosen automatically.
+pub const MIN_BATCH_TIME: Duration = Duration::from_micros(100);
+
+/// Running mean and variance, using Welford's algorithm.
+#[derive(Debug, Default)]
+struct Stats {
+    n: f64,
+    mean: f64,
+    q: f64,
+}
+
+impl Stats {
+    fn update(&mut self, x: f64) {
         self.n += 1.;
         let p = x - self.mean;
         self.mean += p / self.n;
         self.q += p * (x - self.mean);
     }
+
+    /// Half-width of the 95% confidence interval of the mean.
+    fn ci95(&self) -> f64 {
+        1.96 * (self.q / (self.n - 1.)).sqrt() / self.n.sqrt()
+    }
 }
 
-const ITERS: (usize, usize) = (20, 5);
+/// Adaptive sampling takes at least this many samples, unless the time
+/// budget runs out first...
+const MIN_ADAPTIVE_SAMPLES: usize = 10;
+/// ...and at most this many.
+const MAX_ADAPTIVE_SAMPLES: usize = 1_000_000;
 
 pub fn bench<O, F: Fn() -> O>(f: F) -> PerfStats {
-    bench_internal(ITERS.0, ITERS.1, &f)
+    bench_with(&PerfBenchOptions::default(), f)
 }
 
 pub fn bench_iters<O, F: Fn() -> O>(iters: usize, f: F) -> PerfStats {
-    bench_internal(iters, iters / 10, &f)
+    let sampling = Sampling::Fixed {
+        iters,
+        warmup: iters / 10,
+    };
+    let options = PerfBenchOptions {
+        sampling,
+        ..Default::default()
+    };
+    bench_with(&options, f)
+}
+
+pub fn bench_with<O, F: Fn() -> O>(options: &PerfBenchOptions, f: F) -> PerfStats {
+    bench_batched(options, || (), |()| f())
+}
+
+/// Like [`bench_with`], but every run of `f` is given a fresh input built by
+/// `setup` outside of the timed region. Inputs of a whole batch are built
+/// before it is timed, so a batch size of one may be needed for large ones.
+pub fn bench_batched<I, O, S, F>(options: &PerfBenchOptions, setup: S, f: F) -> PerfStats
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+{
+    bench_batched_with_teardown(options, setup, f, drop)
+}
+
+/// Like [`bench`], but each iteration builds the input of `f` with `setup`
+/// and consumes its output with `teardown`, neither of which is timed. The
+/// time of `teardown` is reported in [`PerfStats::drop_ns`] instead.
+pub fn bench_with_setup<I, O, S, F, T>(setup: S, f: F, teardown: T) -> PerfStats
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+    T: Fn(O),
+{
+    bench_batched_with_teardown(&PerfBenchOptions::default(), setup, f, |outputs| {
+        outputs.into_iter().for_each(&teardown)
+    })
+}
+
+/// Like [`bench_batched`], with the outputs of each batch consumed by
+/// `teardown` instead of being dropped.
+fn bench_batched_with_teardown<I, O, S, F, T>(
+    options: &PerfBenchOptions,
+    setup: S,
+    f: F,
+    teardown: T,
+) -> PerfStats
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+    T: Fn(Vec<O>),
+{
+    let batch = options
+        .batch
+        .unwrap_or_else(|| batch_size(&setup, &f, &teardown));
+    let counters = match options.counters {
+        true => CounterGroup::open()
+            .map_err(|e| log!("warning: hardware counters are unavailable: {e}"))
+            .ok(),
+        false => None,
+    };
+    let stats = bench_internal(options, || {
+        let sample = sample_batch(
+            batch,
+            options.clock,
+            counters.as_ref(),
+            &setup,
+            &f,
+            &teardown,
+        );
+        let sample = Sample {
+            time: sample.time.div_f64(batch as f64),
+            drop: sample.drop.div_f64(batch as f64),
+            counters: sample.counters.map(|c| c / batch as u64),
+        };
+        match options.include_drop {
+            true => Sample {
+                time: sample.time + sample.drop,
+                ..sample
+            },
+            false => sample,
+        }
+    });
+    warn_if_overhead(&stats, batch, options.clock);
+    stats
+}
+
+/// Smallest power of two such that that many runs of `f`, with the teardown
+/// of their outputs, last [`MIN_BATCH_TIME`]. The teardown is counted so that
+/// a slow one doesn't grow batches of a fast `f` without bound.
+fn batch_size<I, O, S, F, T>(setup: S, f: F, teardown: T) -> usize
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+    T: Fn(Vec<O>),
+{
+    let mut batch = 1;
+    loop {
+        let sample = sample_batch(batch, Clock::Wall, None, &setup, &f, &teardown);
+        if sample.time + sample.drop >= MIN_BATCH_TIME || batch >= 1 << 20 {
+            return batch;
+        }
+        batch *= 2;
+    }
+}
+
+/// Stats of timing an empty closure in batches of `batch`.
+fn overhead(batch: usize, clock: Clock) -> PerfStats {
+    let options = PerfBenchOptions {
+        clock,
+        ..Default::default()
+    };
+    bench_internal(&options, || {
+        let sample = sample_batch(batch, clock, None, || (), |()| (), drop);
+        Sample {
+            time: sample.time.div_f64(batch as f64),
+            drop: sample.drop.div_f64(batch as f64),
+            ..sample
+        }
+    })
+}
+
+/// Whether `stats` are within three standard deviations of `overhead`.
+fn within_overhead(stats: &PerfStats, overhead: &PerfStats) -> bool {
+    stats.mean_ns <= overhead.mean_ns + 3 * overhead.stdev_ns
+}
+
+fn warn_if_overhead(stats: &PerfStats, batch: usize, clock: Clock) {
+    let overhead = overhead(batch, clock);
+    if within_overhead(stats, &overhead) {
+        log!(
+            "warning: measured time is indistinguishable from the overhead of an empty closure ({overhead}); the benchmarked code may have been optimized away, consider passing its inputs through `black_box`"
+        );
+    }
+}
+
+fn bench_internal<S: FnMut() -> Sample>(options: &PerfBenchOptions, mut sample: S) -> PerfStats {
+    let samples = match options.sampling {
+        Sampling::Fixed { iters, warmup } => sample_fixed(iters, warmup, &mut sample),
+        Sampling::Adaptive {
+            precision,
+            time_budget,
+        } => sample_adaptive(precision, time_budget, &mut sample),
+    };
+    let drop = samples.iter().map(|s| s.drop).sum::<Duration>() / samples.len().max(1) as u32;
+    let counters = match options.counters {
+        false => Counters::NotRequested,
+        true => samples
+            .iter()
+            .try_fold(HardwareCounters::default(), |sum, s| {
+                Some(sum + s.counters?)
+            })
+            .filter(|_| !samples.is_empty())
+            .map_or(Counters::Unavailable, |sum| {
+                Counters::Counted(sum / samples.len() as u64)
+            }),
+    };
+    let times = samples.iter().map(|s| s.time).collect::<Vec<_>>();
+    let (outliers, times) = classify(&times, options.outliers);
+    let mean = times.iter().sum::<Duration>().as_secs_f64() / times.len() as f64;
+    let per_sec = |work: u64| (mean > 0.0).then(|| (work as f64 / mean) as u64);
+    let (bytes_per_sec, elements_per_sec) = match options.throughput {
+        Some(Throughput::Bytes(bytes)) => (per_sec(bytes), None),
+        Some(Throughput::Elements(elements)) => (None, per_sec(elements)),
+        None => (None, None),
+    };
+    PerfStats {
+        drop_ns: nanos(drop.as_secs_f64()),
+        clock: options.clock.resolve(),
+        bytes_per_sec,
+        elements_per_sec,
+        counters,
+        outliers,
+        ..PerfStats::from_samples(&times)
+    }
 }
 
-fn bench_internal<O, F: Fn() -> O>(iters: usize, wu_cd_iters: usize, f: &F) -> PerfStats {
+fn sample_fixed<S: FnMut() -> Sample>(
+    iters: usize,
+    wu_cd_iters: usize,
+    sample: &mut S,
+) -> Vec<Sample> {
     assert!(iters >= 20, "Number of iterations is too low");
-    assert!(iters / wu_cd_iters > 3, "Warm-up/cool-down is too long");
-    let mut stats = Stats::new();
+    assert!(iters >= 4 * wu_cd_iters, "Warm-up/cool-down is too long");
+    let mut samples = Vec::with_capacity(iters - 2 * wu_cd_iters);
     for i in 0..iters {
-        let time = duration_of(f);
+        let time = sample();
         if i >= wu_cd_iters && i < iters - wu_cd_iters {
-            stats.update(time);
+            samples.push(time);
         }
     }
-    stats.into()
+    samples
 }
 
+fn sample_adaptive<S: FnMut() -> Sample>(
+    precision: f64,
+    time_budget: Duration,
+    sample: &mut S,
+) -> Vec<Sample> {
+    let start = Instant::now();
+    while start.elapsed() < time_budget / 10 {
+        sample();
+    }
+
+    let mut samples = Vec::new();
+    let mut stats = Stats::default();
+    while samples.len() < MAX_ADAPTIVE_SAMPLES {
+        let next = sample();
+        samples.push(next);
+        stats.update(next.time.as_secs_f64());
+        // the budget wins over the minimum number of samples
+        if start.elapsed() >= time_budget
+            || samples.len() >= MIN_ADAPTIVE_SAMPLES && stats.ci95() <= precision * stats.mean
+        {
+            break;
+        }
+    }
+    samples
+}
+
+/// Optimization barrier for benchmark inputs, so that the compiler can't
+/// constant-fold them into the benchmarked code. Outputs are passed through
+/// it automatically.
+pub use std::hint::black_box;
 use std::time::Duration;
 
+use derive_builder::Builder;
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
+use super::{
+    clock::Clock,
+    counters::{CounterGroup, Counters, HardwareCounters},
+    outliers::{classify, OutlierCounts, OutlierFilter},
+    significance::Moments,
+};
+
 pub fn duration_of<F: Fn() -> O, O>(f: F) -> Duration {
     let then = Instant::now();
-    let _ = f();
+    black_box(f());
     Instant::now() - then
 }
+
+/// Time of computing and of dropping the outputs of one sample.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    time: Duration,
+    drop: Duration,
+    counters: Option<HardwareCounters>,
+}
+
+/// Total durations of `batch` runs of `f` on inputs built by `setup`, and
+/// of consuming all their outputs with `teardown`, measured on `clock`. Also
+/// counts the hardware events of the runs with `counters`.
+///
+/// The outputs are kept until the whole batch has run and then dropped
+/// together, so that neither duration pays for reading the clock per run.
+fn sample_batch<I, O, S, F, T>(
+    batch: usize,
+    clock: Clock,
+    counters: Option<&CounterGroup>,
+    setup: S,
+    f: F,
+    teardown: T,
+) -> Sample
+where
+    S: Fn() -> I,
+    F: Fn(I) -> O,
+    T: Fn(Vec<O>),
+{
+    let inputs = (0..batch).map(|_| black_box(setup())).collect::<Vec<_>>();
+    let mut outputs = Vec::with_capacity(batch);
+    // started inside `measure`, not to time its syscalls
+    let timed_run = || {
+        let stopwatch = clock.start();
+        for input in inputs {
+            outputs.push(black_box(f(input)));
+        }
+        stopwatch.elapsed()
+    };
+    let (time, counters) = match counters {
+        Some(group) => {
+            let (elapsed, counters) = group.measure(timed_run);
+            (elapsed, counters.ok())
+        }
+        None => (timed_run(), None),
+    };
+    let stopwatch = clock.start();
+    teardown(outputs);
+    Sample {
+        time,
+        drop: stopwatch.elapsed(),
+        counters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution() {
+        let samples = (1..=100)
+            .rev()
+            .map(Duration::from_nanos)
+            .collect::<Vec<_>>();
+        let stats = PerfStats::from_samples(&samples);
+        assert_eq!(stats.n, 100);
+        assert_eq!(stats.mean_ns, 50);
+        assert_eq!(stats.stdev_ns, 29);
+        assert_eq!((stats.min_ns, stats.max_ns), (1, 100));
+        assert_eq!(stats.median_ns, 50);
+        assert_eq!((stats.p90_ns, stats.p95_ns, stats.p99_ns), (90, 95, 99));
+        assert_eq!(stats.moments.n, 100);
+        assert!((stats.moments.mean - 50.5).abs() < 1e-6);
+
+        // baselines don't grow with the number of samples
+        let many = vec![Duration::from_nanos(10); 100_000];
+        assert!(
+            toml::to_string(&PerfStats::from_samples(&many))
+                .unwrap()
+                .len()
+                < 1000
+        );
+
+        let stats = PerfStats::from_samples(&[Duration::from_nanos(7)]);
+        assert_eq!((stats.n, stats.stdev_ns, stats.p99_ns), (1, 0, 7));
+    }
+
+    #[test]
+    fn microsecond_baseline_rejected() {
+        assert!(toml::from_str::<PerfStats>("mean = 12\nstdev = 1").is_err());
+        let stats = toml::from_str::<PerfStats>("mean_ns = 12000").unwrap();
+        assert_eq!(stats.mean_ns, 12000);
+    }
+
+    #[test]
+    fn counters_requested() {
+        assert_eq!(bench(|| ()).counters, Counters::NotRequested);
+        let options = PerfBenchOptionsBuilder::default()
+            .counters(true)
+            .build()
+            .unwrap();
+        let stats = bench_with(&options, || ());
+        assert_ne!(stats.counters, Counters::NotRequested);
+
+        for counters in [
+            Counters::NotRequested,
+            Counters::Unavailable,
+            Counters::Counted(HardwareCounters {
+                instructions: 7,
+                ..Default::default()
+            }),
+        ] {
+            let stats = PerfStats {
+                counters,
+                ..Default::default()
+            };
+            let stats = toml::from_str::<PerfStats>(&toml::to_string(&stats).unwrap()).unwrap();
+            assert_eq!(stats.counters, counters);
+        }
+    }
+
+    #[test]
+    fn throughput() {
+        let options = PerfBenchOptionsBuilder::default()
+            .throughput(Some(Throughput::Bytes(1 << 20)))
+            .build()
+            .unwrap();
+        let stats = bench_internal(&options, || Sample {
+            time: Duration::from_millis(10),
+            drop: Duration::ZERO,
+            counters: None,
+        });
+        assert_eq!(stats.bytes_per_sec, Some(100 << 20));
+        assert_eq!(stats.elements_per_sec, None);
+        assert!(stats.to_string().contains("100.00 MiB/s"));
+    }
+
+    #[test]
+    fn overhead_detection() {
+        let overhead = PerfStats {
+            mean_ns: 20,
+            stdev_ns: 2,
+            ..Default::default()
+        };
+        let stats = |mean_ns| PerfStats {
+            mean_ns,
+            ..Default::default()
+        };
+        assert!(within_overhead(&stats(25), &overhead));
+        assert!(!within_overhead(&stats(30), &overhead));
+    }
+
+    #[test]
+    fn overhead_per_run() {
+        // the drop of the whole batch is spread over its runs, like the time
+        let overhead = overhead(1 << 10, Clock::Wall);
+        assert!(overhead.drop_ns <= 1, "{overhead}");
+    }
+
+    #[test]
+    fn drop_timing() {
+        let stats = bench(|| vec![1_u8; 1 << 20]);
+        assert!(stats.drop_ns > 0);
+        // the teardown is timed as the drop
+        let stats = bench_with_setup(|| (), |()| (), |()| std::thread::sleep(MIN_BATCH_TIME));
+        assert!(stats.drop_ns >= MIN_BATCH_TIME.as_nanos() as u64);
+        assert!(stats.mean_ns < MIN_BATCH_TIME.as_nanos() as u64, "{stats}");
+    }
+
+    #[test]
+    fn outputs_dropped_after_batch() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+        struct Output;
+        impl Drop for Output {
+            fn drop(&mut self) {
+                LIVE.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        let options = PerfBenchOptionsBuilder::default()
+            .batch(Some(16))
+            .build()
+            .unwrap();
+        // inputs of a batch are built once the previous one is dropped
+        bench_batched(
+            &options,
+            || assert_eq!(LIVE.load(Ordering::Relaxed), 0),
+            |()| {
+                assert!(LIVE.fetch_add(1, Ordering::Relaxed) < 16);
+                Output
+            },
+        );
+    }
+
+    #[test]
+    fn no_drop_cost() {
+        for clock in [Clock::Wall, Clock::ThreadCpu] {
+            let options = PerfBenchOptionsBuilder::default()
+                .clock(clock)
+                .build()
+                .unwrap();
+            let stats = bench_with(&options, || black_box(42_u64));
+            assert!(stats.drop_ns <= 1, "{clock}: {stats}");
+        }
+    }
+
+    #[test]
+    fn fresh_inputs() {
+        let stats = bench_batched(
+            &PerfBenchOptions::default(),
+            || (0..100).rev().collect::<Vec<u32>>(),
+            |mut v| {
+                v.sort();
+                assert_eq!(v[0], 0);
+                v
+            },
+        );
+        assert!(stats.n > 0);
+    }
+
+    #[test]
+    fn batching() {
+        assert!(batch_size(|| (), |()| (), drop) > 1);
+        let options = PerfBenchOptionsBuilder::default()
+            .clock(Clock::ThreadCpu)
+            .build()
+            .unwrap();
+        assert_eq!(
+            bench_with(&options, || ()).clock,
+            Clock::ThreadCpu.resolve()
+        );
+        assert_eq!(
+            batch_size(|| (), |()| std::thread::sleep(MIN_BATCH_TIME), drop),
+            1
+        );
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        let sample = |micros: fn(usize) -> u64| {
+            let mut i = 0;
+            move || {
+                i += 1;
+                Sample {
+                    time: Duration::from_micros(micros(i)),
+                    drop: Duration::ZERO,
+                    counters: None,
+                }
+            }
+        };
+        let budget = Duration::from_millis(50);
+
+        // constant samples converge as soon as possible
+        let samples = sample_adaptive(0.01, budget, &mut sample(|_| 10));
+        assert_eq!(samples.len(), MIN_ADAPTIVE_SAMPLES);
+
+        // noisy samples need more
+        let samples = sample_adaptive(0.01, budget, &mut sample(|i| 10 + (i % 2) as u64 * 5));
+        assert!(samples.len() > MIN_ADAPTIVE_SAMPLES);
+
+        // slow samples stop at the budget
+        let start = Instant::now();
+        let samples = sample_adaptive(0.01, budget, &mut || {
+            std::thread::sleep(budget / 4);
+            Sample {
+                time: budget / 4,
+                drop: Duration::ZERO,
+                counters: None,
+            }
+        });
+        assert!(samples.len() < MIN_ADAPTIVE_SAMPLES);
+        assert!(start.elapsed() < budget * 2);
+    }
+
+    #[test]
+    fn no_warmup() {
+        let options = PerfBenchOptionsBuilder::default()
+            .sampling(Sampling::Fixed {
+                iters: 20,
+                warmup: 0,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(bench_with(&options, || ()).n, 20);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod benchmark;
+pub mod clock;
 pub mod compare;
+pub mod counters;
 pub mod measure;
+pub mod outliers;
+pub mod significance;
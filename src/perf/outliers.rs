@@ -0,0 +1,128 @@
+//! Classification of samples using Tukey's fences.
+
+use std::time::Duration;
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Number of samples outside of the inner (mild) and outer (severe) fences,
+/// at `1.5` and `3` interquartile ranges from the quartiles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[display(
+    fmt = "outliers: {low_severe} low severe, {low_mild} low mild, {high_mild} high mild, {high_severe} high severe"
+)]
+pub struct OutlierCounts {
+    pub low_severe: u64,
+    pub low_mild: u64,
+    pub high_mild: u64,
+    pub high_severe: u64,
+}
+
+impl OutlierCounts {
+    pub fn mild(&self) -> u64 {
+        self.low_mild + self.high_mild
+    }
+
+    pub fn severe(&self) -> u64 {
+        self.low_severe + self.high_severe
+    }
+}
+
+/// Which outliers are excluded from the statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutlierFilter {
+    #[default]
+    Keep,
+    ExcludeSevere,
+    ExcludeAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    LowSevere,
+    LowMild,
+    Normal,
+    HighMild,
+    HighSevere,
+}
+
+/// Linearly interpolated quantile of sorted values.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q * (sorted.len() - 1) as f64;
+    let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+}
+
+/// Counts the outliers among `samples` and returns the samples remaining
+/// after applying `filter`.
+pub fn classify(samples: &[Duration], filter: OutlierFilter) -> (OutlierCounts, Vec<Duration>) {
+    if samples.len() < 4 {
+        return (OutlierCounts::default(), samples.to_vec());
+    }
+    let mut sorted = samples
+        .iter()
+        .map(Duration::as_secs_f64)
+        .collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+    let (q1, q3) = (quantile(&sorted, 0.25), quantile(&sorted, 0.75));
+    let iqr = q3 - q1;
+    let class = |x: f64| match x {
+        _ if x < q1 - 3.0 * iqr => Class::LowSevere,
+        _ if x < q1 - 1.5 * iqr => Class::LowMild,
+        _ if x > q3 + 3.0 * iqr => Class::HighSevere,
+        _ if x > q3 + 1.5 * iqr => Class::HighMild,
+        _ => Class::Normal,
+    };
+
+    let mut counts = OutlierCounts::default();
+    let mut kept = Vec::with_capacity(samples.len());
+    for &sample in samples {
+        let class = class(sample.as_secs_f64());
+        match class {
+            Class::LowSevere => counts.low_severe += 1,
+            Class::LowMild => counts.low_mild += 1,
+            Class::Normal => {}
+            Class::HighMild => counts.high_mild += 1,
+            Class::HighSevere => counts.high_severe += 1,
+        }
+        let keep = match filter {
+            OutlierFilter::Keep => true,
+            OutlierFilter::ExcludeSevere => !matches!(class, Class::LowSevere | Class::HighSevere),
+            OutlierFilter::ExcludeAll => class == Class::Normal,
+        };
+        if keep {
+            kept.push(sample);
+        }
+    }
+    (counts, kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tukey_fences() {
+        // quartiles are 10 and 12
+        let mut samples = [10, 10, 11, 11, 11, 12, 12, 12, 5, 16, 100]
+            .map(Duration::from_micros)
+            .to_vec();
+        samples.sort();
+        let (counts, kept) = classify(&samples, OutlierFilter::Keep);
+        assert_eq!(
+            counts,
+            OutlierCounts {
+                low_severe: 1,
+                low_mild: 0,
+                high_mild: 1,
+                high_severe: 1,
+            }
+        );
+        assert_eq!(kept.len(), samples.len());
+
+        let (_, kept) = classify(&samples, OutlierFilter::ExcludeSevere);
+        assert_eq!(kept.len(), samples.len() - 2);
+        let (_, kept) = classify(&samples, OutlierFilter::ExcludeAll);
+        assert_eq!(kept.len(), samples.len() - 3);
+    }
+}
@@ -0,0 +1,195 @@
+//! Welch's t-test comparing measured samples with baseline samples.
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Summary of a set of samples, all that Welch's t-test needs of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Moments {
+    pub n: u64,
+    pub mean: f64,
+    /// Unbiased variance.
+    pub variance: f64,
+}
+
+impl Moments {
+    pub fn of(samples: &[f64]) -> Self {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n.max(1.0);
+        let variance = match samples.len() {
+            0 | 1 => 0.0,
+            _ => samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+        Moments {
+            n: samples.len() as u64,
+            mean,
+            variance,
+        }
+    }
+}
+
+/// Outcome of comparing the means of two sets of samples.
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[display(
+    fmt = "change = {:+.2}% [{:+.2}%, {:+.2}%] (p = {p_value:.4})",
+    "change * 100.0",
+    "ci95.0 * 100.0",
+    "ci95.1 * 100.0"
+)]
+pub struct Significance {
+    /// Relative change of the mean, `(value - ref_value) / ref_value`.
+    pub change: f64,
+    /// 95% confidence interval of `change`.
+    pub ci95: (f64, f64),
+    /// Two-sided p-value of the hypothesis that the means are equal.
+    pub p_value: f64,
+}
+
+impl Significance {
+    pub fn is_significant(&self, alpha: f64) -> bool {
+        self.p_value < alpha
+    }
+}
+
+/// Runs Welch's t-test on `samples` against `ref_samples`.
+///
+/// Returns `None` unless both sets have at least two samples and the
+/// reference mean is positive.
+pub fn welch_t_test(samples: &Moments, ref_samples: &Moments) -> Option<Significance> {
+    if samples.n < 2 || ref_samples.n < 2 || ref_samples.mean <= 0.0 {
+        return None;
+    }
+    let (n, mean, var) = (samples.n as f64, samples.mean, samples.variance);
+    let (ref_n, ref_mean, ref_var) = (ref_samples.n as f64, ref_samples.mean, ref_samples.variance);
+
+    let diff = mean - ref_mean;
+    let (se_sq, ref_se_sq) = (var / n, ref_var / ref_n);
+    let se = (se_sq + ref_se_sq).sqrt();
+    let (p_value, margin) = if se == 0.0 {
+        (if diff == 0.0 { 1.0 } else { 0.0 }, 0.0)
+    } else {
+        // Welch-Satterthwaite degrees of freedom
+        let df = (se_sq + ref_se_sq).powi(2)
+            / (se_sq.powi(2) / (n - 1.0) + ref_se_sq.powi(2) / (ref_n - 1.0));
+        (t_p_value(diff / se, df), t_critical(0.05, df) * se)
+    };
+    Some(Significance {
+        change: diff / ref_mean,
+        ci95: ((diff - margin) / ref_mean, (diff + margin) / ref_mean),
+        p_value,
+    })
+}
+
+/// Two-sided p-value of `t` in Student's t-distribution with `df` degrees of
+/// freedom.
+fn t_p_value(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// `t` such that the two-sided p-value is `alpha`.
+fn t_critical(alpha: f64, df: f64) -> f64 {
+    let (mut lo, mut hi) = (0.0, 1e3);
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if t_p_value(mid, df) > alpha {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Regularized incomplete beta function `I_x(a, b)`.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // the continued fraction converges quickly only on this side
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function, by the modified Lentz
+/// method.
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        for coef in [even, odd] {
+            d = 1.0 / clamp(1.0 + coef * d);
+            c = clamp(1.0 + coef / c);
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Logarithm of the gamma function, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFS
+        .iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |acc, (i, c)| {
+            acc + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_distribution() {
+        // two-sided 5% critical values
+        assert!((t_critical(0.05, 1.0) - 12.706).abs() < 1e-3);
+        assert!((t_critical(0.05, 10.0) - 2.228).abs() < 1e-3);
+        assert!((t_p_value(0.0, 5.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn welch() {
+        let base = [100., 102., 98., 101., 99., 100., 103., 97., 100., 100.];
+        let same = [101., 99., 100., 102., 98., 100., 97., 103., 100., 100.];
+        let slower = base.map(|x| x + 10.);
+        let (base, same, slower) = (Moments::of(&base), Moments::of(&same), Moments::of(&slower));
+
+        let s = welch_t_test(&same, &base).unwrap();
+        assert!(!s.is_significant(0.05));
+        assert!(s.ci95.0 < 0.0 && s.ci95.1 > 0.0);
+
+        let s = welch_t_test(&slower, &base).unwrap();
+        assert!(s.is_significant(0.05));
+        assert!((s.change - 0.1).abs() < 1e-9);
+        assert!(s.ci95.0 > 0.0 && s.ci95.1 > s.change);
+
+        assert!(welch_t_test(&slower, &Moments::of(&[100.])).is_none());
+    }
+}
@@ -35,11 +35,15 @@ where
 }
 
 #[derive(Debug, Error)]
-#[error("{value} exceeds {ref_value} by more than {limit}")]
+#[error(
+    "{value} {} {ref_value} by more than {limit}",
+    if *decrease { "falls below" } else { "exceeds" }
+)]
 pub struct ThresholdError<T: Display + Integer + ToBigInt + ToPrimitive + Clone> {
     limit: Threshold<T>,
     value: T,
     ref_value: T,
+    decrease: bool,
 }
 
 impl<T> Threshold<T>
@@ -51,8 +55,10 @@ where
     }
 
     fn check_ratio(ratio: &Ratio<T>, value: &T, ref_value: &T) -> bool {
+        // any increase over zero is infinitely large
         value.clone() <= ref_value.clone()
-            || Ratio::new(value.clone() - ref_value.clone(), ref_value.clone()) <= *ratio
+            || !ref_value.is_zero()
+                && Ratio::new(value.clone() - ref_value.clone(), ref_value.clone()) <= *ratio
     }
 
     pub fn check(&self, value: &T, ref_value: &T) -> Result<(), ThresholdError<T>> {
@@ -61,17 +67,41 @@ where
                 limit: self.clone(),
                 value: value.clone(),
                 ref_value: ref_value.clone(),
+                decrease: false,
             }),
             Threshold::Ratio(ratio) if !Self::check_ratio(ratio, value, ref_value) => {
                 Err(ThresholdError {
                     limit: self.clone(),
                     value: value.clone(),
                     ref_value: ref_value.clone(),
+                    decrease: false,
                 })
             }
             _ => Ok(()),
         }
     }
+
+    /// Like [`check`](Self::check), for values where lower is worse: fails if
+    /// `value` falls below `ref_value` by more than the limit.
+    pub fn check_decrease(&self, value: &T, ref_value: &T) -> Result<(), ThresholdError<T>> {
+        let ok = match self {
+            Threshold::None => true,
+            Threshold::Cap(cap) => value.clone() + cap.clone() >= ref_value.clone(),
+            Threshold::Ratio(ratio) => {
+                value >= ref_value
+                    || Ratio::new(ref_value.clone() - value.clone(), ref_value.clone()) <= *ratio
+            }
+        };
+        match ok {
+            true => Ok(()),
+            false => Err(ThresholdError {
+                limit: self.clone(),
+                value: value.clone(),
+                ref_value: ref_value.clone(),
+                decrease: true,
+            }),
+        }
+    }
 }
 
 pub trait ThresholdFor<T> {
@@ -166,7 +196,7 @@ where
     T: Serialize + Deserialize<'a>,
     <H as ThresholdFor<T>>::Error: Debug + Display,
 {
-    let ref_value = toml::from_str(&baseline)?;
+    let ref_value = toml::from_str(baseline)?;
     let value = f();
     threshold
         .check_threshold(&value, &ref_value)
@@ -178,9 +208,9 @@ where
 struct MemBenchArgs {
     #[arg(short, long, value_name = "DIR", env)]
     load_baseline: Option<PathBuf>,
-    #[arg(short, long, value_name = "DIR")]
+    #[arg(short, long, value_name = "DIR", env)]
     save_baseline: Option<PathBuf>,
-    #[arg(short, long)]
+    #[arg(short, long, env)]
     discard_baseline: bool,
 }
 
@@ -216,7 +246,7 @@ fn cargo_target_directory() -> Option<PathBuf> {
         .map(PathBuf::from)
         .or_else(|| {
             let output = Command::new(env::var_os("CARGO")?)
-                .args(&["metadata", "--format-version", "1"])
+                .args(["metadata", "--format-version", "1"])
                 .output()
                 .ok()?;
             let metadata: Metadata = serde_json::from_slice(&output.stdout).ok()?;
@@ -225,9 +255,7 @@ fn cargo_target_directory() -> Option<PathBuf> {
 }
 
 fn default_dir(dir: &str) -> PathBuf {
-    cargo_target_directory()
-        .unwrap_or_else(PathBuf::new)
-        .join(dir)
+    cargo_target_directory().unwrap_or_default().join(dir)
 }
 
 const EXT: &str = "toml";
@@ -244,7 +272,42 @@ where
     T: Debug + Serialize + DeserializeOwned,
     <H as ThresholdFor<T>>::Error: Debug + Display,
 {
-    let args = parse_args();
+    check_threshold_with_parsed_args(parse_args(), f, dir, id, threshold)
+}
+
+/// Like [`check_threshold_with_args`], for benchmarks run from a `#[test]`
+/// through [`run_test`](crate::harness::run_test). The command line belongs to
+/// the test harness, so the options are only read from the environment
+/// (`LOAD_BASELINE`, `SAVE_BASELINE`, `DISCARD_BASELINE`).
+pub fn check_threshold_in_test<F, H, T>(
+    f: F,
+    dir: &str,
+    id: &str,
+    threshold: &H,
+) -> Result<T, CheckThresholdError<H::Error>>
+where
+    F: Fn() -> T,
+    H: ThresholdFor<T>,
+    T: Debug + Serialize + DeserializeOwned,
+    <H as ThresholdFor<T>>::Error: Debug + Display,
+{
+    let args = MemBenchArgs::parse_from([env!("CARGO_PKG_NAME")]);
+    check_threshold_with_parsed_args(args, f, dir, id, threshold)
+}
+
+fn check_threshold_with_parsed_args<F, H, T>(
+    args: MemBenchArgs,
+    f: F,
+    dir: &str,
+    id: &str,
+    threshold: &H,
+) -> Result<T, CheckThresholdError<H::Error>>
+where
+    F: Fn() -> T,
+    H: ThresholdFor<T>,
+    T: Debug + Serialize + DeserializeOwned,
+    <H as ThresholdFor<T>>::Error: Debug + Display,
+{
     let (baseline, load_prev, strict_compare, save_new) = match args {
         MemBenchArgs {
             load_baseline: Some(baseline),
@@ -294,5 +357,20 @@ mod tests {
         assert!(l.check(&111, &r).is_err());
 
         println!("{}", l.check(&111, &r).unwrap_err());
+
+        assert!(l.check(&0, &0).is_ok());
+        assert!(l.check(&1, &0).is_err());
+    }
+
+    #[test]
+    fn limit_decrease() {
+        let l = Threshold::ratio(1, 10);
+        let r = 100_u32;
+        assert!(l.check_decrease(&200, &r).is_ok());
+        assert!(l.check_decrease(&90, &r).is_ok());
+        assert!(l.check_decrease(&89, &r).is_err());
+        assert!(Threshold::cap(10).check_decrease(&89, &r).is_err());
+
+        println!("{}", l.check_decrease(&89, &r).unwrap_err());
     }
 }
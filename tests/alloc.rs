@@ -0,0 +1,179 @@
+//! Tests needing the tracing allocator as the global allocator.
+
+use std::{
+    alloc::System,
+    panic,
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+use alloc_test::{
+    alloc::{
+        allocator::TracingAllocator,
+        benchmark::{alloc_benchmark_report, alloc_benchmark_with_setup, AllocBenchOptionsBuilder},
+        default_tracing_allocator,
+        measure::{trace_allocs, trace_allocs_with, MemoryTracingHooks, TraceOptionsBuilder},
+        no_alloc::{assert_no_alloc, NoAllocGuard, NoAllocMode},
+    },
+    complexity::{measure_complexity, Complexity, Metric},
+};
+
+#[global_allocator]
+static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+
+/// Allocations of all threads are traced, so tests must not run concurrently.
+fn serial() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[test]
+fn panic_while_capturing_peak() {
+    let _serial = serial();
+    let options = TraceOptionsBuilder::default()
+        .capture_peak(true)
+        .build()
+        .unwrap();
+    let result = panic::catch_unwind(|| {
+        trace_allocs_with(&options, || {
+            let _v = Vec::<u8>::with_capacity(100);
+            panic!("traced closure panicked");
+        })
+    });
+    assert!(result.is_err());
+
+    // tracing was ended by the unwinding
+    let (_, stats) = trace_allocs(|| vec![0_u8; 10]);
+    assert_eq!(stats.peak, 10);
+}
+
+fn panic_message<F: FnOnce() + panic::UnwindSafe>(f: F) -> Option<String> {
+    let payload = panic::catch_unwind(f).err()?;
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+}
+
+#[test]
+fn no_alloc_panic() {
+    let _serial = serial();
+    let mut v = Vec::<u8>::with_capacity(1);
+    assert_no_alloc(|| v.push(1));
+    let message = panic_message(move || assert_no_alloc(|| v.extend([2, 3, 4, 5]))).unwrap();
+    assert!(message.starts_with("1 allocations in a no-alloc region"));
+    assert!(message.contains("no_alloc_panic"));
+}
+
+#[test]
+fn nested_no_alloc() {
+    let _serial = serial();
+    // allocations in an inner counting region
+    let message = panic_message(|| {
+        assert_no_alloc(|| {
+            let guard = NoAllocGuard::new(NoAllocMode::Count);
+            let _v = Vec::<u8>::with_capacity(64);
+            assert_eq!(guard.allocations(), 1);
+        })
+    })
+    .unwrap();
+    assert!(message.contains("nested_no_alloc"));
+
+    // allocations before an inner counting region
+    let message = panic_message(|| {
+        assert_no_alloc(|| {
+            let _v = Vec::<u8>::with_capacity(64);
+            let guard = NoAllocGuard::new(NoAllocMode::Count);
+            assert_eq!(guard.allocations(), 0);
+        })
+    })
+    .unwrap();
+    assert!(message.starts_with("1 allocations"));
+
+    // the inner region doesn't panic, the outer one counts both
+    let message = panic_message(|| {
+        let _outer = NoAllocGuard::new(NoAllocMode::Panic);
+        let _v = Vec::<u8>::with_capacity(64);
+        {
+            let _inner = NoAllocGuard::new(NoAllocMode::Log);
+            let _w = Vec::<u8>::with_capacity(64);
+        }
+    })
+    .unwrap();
+    assert!(message.starts_with("2 allocations"));
+
+    // the report of a logging region is not counted by the enclosing one
+    let outer = NoAllocGuard::new(NoAllocMode::Count);
+    {
+        let _inner = NoAllocGuard::new(NoAllocMode::Log);
+        let _v = Vec::<u8>::with_capacity(64);
+    }
+    assert_eq!(outer.allocations(), 1);
+    drop(outer);
+
+    assert!(panic_message(|| {
+        let _outer = NoAllocGuard::new(NoAllocMode::Panic);
+        let _inner = NoAllocGuard::new(NoAllocMode::Count);
+    })
+    .is_none());
+}
+
+#[test]
+fn warmup_excludes_initialization() {
+    let _serial = serial();
+    static TABLE: OnceLock<Vec<u32>> = OnceLock::new();
+    let options = AllocBenchOptionsBuilder::default()
+        .warmup(2)
+        .build()
+        .unwrap();
+    let report = alloc_benchmark_report("lazy_table", &options, || {
+        TABLE.get_or_init(|| (0..100).collect()).len()
+    });
+    let first_run = report.first_run.unwrap();
+    assert_eq!((first_run.total_num, first_run.total_size), (1, 400));
+    assert_eq!((report.stats.total_num, report.stats.total_size), (0, 0));
+}
+
+#[test]
+fn setup_consumed_by_f() {
+    let _serial = serial();
+    let stats = alloc_benchmark_with_setup(
+        "consume",
+        || vec![0_u8; 1000],
+        |input| {
+            let output = vec![1_u8; 100];
+            drop(input);
+            output
+        },
+        drop,
+    );
+    assert_eq!(
+        (stats.current, stats.peak, stats.total_size),
+        (100, 100, 100)
+    );
+
+    // the input is reallocated: the new allocation is traced, freeing the
+    // old one is not
+    let stats = alloc_benchmark_with_setup(
+        "grow",
+        || Vec::<u8>::with_capacity(1000),
+        |mut input| {
+            input.extend([1; 1001]);
+            input
+        },
+        drop,
+    );
+    assert_eq!(stats.current, stats.total_size);
+    assert!(stats.current >= 1001);
+}
+
+#[test]
+fn complexity_of_consuming_f() {
+    let _serial = serial();
+    let sizes = [1000, 2000, 4000, 8000];
+    let report = measure_complexity(&sizes, |n| vec![0_u8; n], |v| v.clone());
+    let current = report.points.iter().map(|p| p.alloc.current);
+    assert!(current.eq(sizes));
+    report.check(Metric::Current, Complexity::Linear).unwrap();
+    report.check(Metric::Peak, Complexity::Linear).unwrap();
+    assert!(report.check(Metric::Current, Complexity::Constant).is_err());
+}
@@ -0,0 +1,82 @@
+//! Tests of the `#[alloc_test]` and `#[perf_test]` attribute macros.
+//!
+//! The annotated functions are ignored as tests of their own: each is called
+//! by a test checking the baseline it saves.
+
+use std::{alloc::System, fs, path::PathBuf, thread};
+
+use alloc_test::{
+    alloc::{
+        allocator::TracingAllocator,
+        default_tracing_allocator,
+        measure::{MemoryStats, MemoryTracingHooks},
+    },
+    alloc_test,
+    perf::measure::PerfStats,
+    perf_test,
+};
+use serde::de::DeserializeOwned;
+
+#[global_allocator]
+static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+
+/// Baseline saved into the default directory `dir` by the benchmark `id`.
+fn baseline<T: DeserializeOwned>(dir: &str, id: &str) -> T {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = target_dir
+        .parent()
+        .unwrap()
+        .join(dir)
+        .join(id)
+        .with_extension("toml");
+    toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+}
+
+#[alloc_test(peak = "10%", total_num = 5)]
+#[ignore = "run by `alloc_test_stats`"]
+fn alloc_vec() -> Vec<u8> {
+    vec![0; 100]
+}
+
+#[test]
+fn alloc_test_stats() {
+    // allocations of other threads of this process are not measured
+    let busy = thread::spawn(|| {
+        for _ in 0..10_000 {
+            std::hint::black_box(vec![0_u8; 10]);
+        }
+    });
+    alloc_vec();
+    busy.join().unwrap();
+
+    let stats: MemoryStats = baseline("alloc_bench", "attributes::alloc_vec");
+    assert_eq!((stats.total_num, stats.total_size), (1, 100));
+    assert_eq!((stats.peak, stats.current, stats.reallocs), (100, 100, 0));
+}
+
+#[perf_test(mean_ns = "2.5%")]
+#[ignore = "run by `perf_test_stats`"]
+fn perf_vec() -> Vec<u8> {
+    vec![0; 100]
+}
+
+#[perf_test(p99_ns = "10%")]
+#[ignore = "run by `perf_test_stats`"]
+fn perf_vec_p99() -> Vec<u8> {
+    vec![0; 100]
+}
+
+#[test]
+fn perf_test_stats() {
+    perf_vec();
+    perf_vec_p99();
+
+    for id in ["attributes::perf_vec", "attributes::perf_vec_p99"] {
+        let stats: PerfStats = baseline("perf_bench", id);
+        assert!(stats.n > 0, "{id}");
+        assert!(stats.mean_ns > 0, "{id}");
+        assert!(stats.min_ns <= stats.median_ns, "{id}");
+        assert!(stats.median_ns <= stats.p99_ns, "{id}");
+        assert!(stats.p99_ns <= stats.max_ns, "{id}");
+    }
+}
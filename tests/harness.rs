@@ -0,0 +1,140 @@
+//! Tests of the `harness = false` runner. This target is its own benchmark
+//! suite: run without arguments, it runs the suite in a child process and
+//! checks the outcome.
+
+use std::{
+    alloc::System,
+    env, fs,
+    path::Path,
+    process::{Command, ExitCode, Output},
+};
+
+use alloc_test::{
+    alloc::{allocator::TracingAllocator, default_tracing_allocator, measure::MemoryTracingHooks},
+    alloc_test,
+    harness::{self, Benchmark},
+};
+
+#[global_allocator]
+static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+
+/// Set for the child process acting as the benchmark runner.
+const RUNNER_VAR: &str = "ALLOC_TEST_HARNESS_RUNNER";
+
+#[alloc_test(total_num = 0)]
+fn one_allocation() -> Vec<u8> {
+    vec![0; 100]
+}
+
+#[alloc_test(total_num = 0)]
+fn two_allocations() -> Vec<Vec<u8>> {
+    vec![vec![0; 100]]
+}
+
+/// Benchmarks of the same name in different modules.
+mod small {
+    #[alloc_test::alloc_test]
+    fn parse() -> Vec<u8> {
+        vec![0; 10]
+    }
+}
+
+mod large {
+    #[alloc_test::alloc_test]
+    fn parse() -> Vec<u8> {
+        vec![0; 1000]
+    }
+}
+
+fn listed() {}
+
+fn run_suite(args: &[&str], baseline_option: &str, baselines: &Path) -> Output {
+    let output = Command::new(env::current_exe().unwrap())
+        .env(RUNNER_VAR, "1")
+        .args(args)
+        .args(["--", baseline_option])
+        .arg(baselines)
+        .output()
+        .unwrap();
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn main() -> ExitCode {
+    if env::var_os(RUNNER_VAR).is_some() {
+        let mut benchmarks = vec![Benchmark {
+            name: "harness::listed",
+            run: listed,
+        }];
+        benchmarks.extend(harness::registered());
+        return harness::run(&benchmarks);
+    }
+
+    // both baselines have a single allocation
+    let baselines = env::temp_dir().join(format!("alloc-test-harness-{}", std::process::id()));
+    fs::create_dir_all(&baselines).unwrap();
+    for name in ["harness::one_allocation", "harness::two_allocations"] {
+        let baseline = "current = 0\npeak = 100\ntotal_size = 100\ntotal_num = 1\nreallocs = 0\n";
+        fs::write(baselines.join(name).with_extension("toml"), baseline).unwrap();
+    }
+
+    let output = run_suite(&["--list"], "--load-baseline", &baselines);
+    let list = stdout(&output);
+    for name in [
+        "listed",
+        "one_allocation",
+        "two_allocations",
+        "small::parse",
+        "large::parse",
+    ] {
+        assert!(
+            list.contains(&format!("harness::{name}: benchmark")),
+            "{name}"
+        );
+    }
+
+    let output = run_suite(&["one_alloc"], "--load-baseline", &baselines);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("bench harness::one_allocation ... ok"));
+    assert!(out.contains("1 passed; 0 failed; 4 filtered out"));
+
+    let output = run_suite(
+        &["harness::listed", "--exact"],
+        "--load-baseline",
+        &baselines,
+    );
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 passed; 0 failed; 4 filtered out"));
+
+    // the threshold failure of the child fails the suite
+    let output = run_suite(&["allocation"], "--load-baseline", &baselines);
+    assert!(!output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("bench harness::one_allocation ... ok"));
+    assert!(out.contains("bench harness::two_allocations ... FAILED"));
+    assert!(out.contains("1 passed; 1 failed; 3 filtered out"));
+
+    // each of the same-named benchmarks runs once, with a baseline of its own
+    let output = run_suite(&["parse"], "--save-baseline", &baselines);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("bench harness::small::parse ... ok"));
+    assert!(out.contains("bench harness::large::parse ... ok"));
+    assert!(out.contains("2 passed; 0 failed; 3 filtered out"));
+    for (name, size) in [("small", 10), ("large", 1000)] {
+        let baseline = baselines.join(format!("harness::{name}::parse.toml"));
+        let baseline = fs::read_to_string(baseline).unwrap();
+        assert!(
+            baseline.contains(&format!("total_size = {size}\n")),
+            "{name}"
+        );
+    }
+
+    fs::remove_dir_all(&baselines).unwrap();
+    ExitCode::SUCCESS
+}
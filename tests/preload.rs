@@ -0,0 +1,111 @@
+//! Tests of the `alloc-test-preload` shim, run in a child process with the
+//! shim in `LD_PRELOAD`.
+
+#![cfg(all(target_os = "linux", target_env = "gnu"))]
+
+use std::{alloc::System, env, path::PathBuf, process::Command, thread};
+
+use alloc_test::alloc::{
+    allocator::TracingAllocator,
+    default_tracing_allocator,
+    measure::{trace_allocs, MemoryTracingHooks},
+    no_alloc::{NoAllocGuard, NoAllocMode},
+};
+
+#[global_allocator]
+static ALLOCATOR: TracingAllocator<MemoryTracingHooks, System> = default_tracing_allocator();
+
+/// Set for the child process.
+const SHIM_VAR: &str = "ALLOC_TEST_PRELOAD_SHIM";
+
+/// Builds the shim in a target directory of its own, as the one of the tests
+/// is locked by the running `cargo test`.
+fn build_shim() -> PathBuf {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("preload");
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["build", "-p", "alloc-test-preload", "--target-dir"])
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the shim failed");
+    target_dir.join("debug/liballoc_test_preload.so")
+}
+
+#[test]
+fn preload_shim() {
+    if env::var_os(SHIM_VAR).is_some() {
+        return;
+    }
+    let shim = build_shim();
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["c_heap_traced", "--test-threads=1"])
+        .env("LD_PRELOAD", &shim)
+        .env(SHIM_VAR, &shim)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "C heap tracing failed with the shim loaded"
+    );
+}
+
+/// Only does something in the child process started by `preload_shim`.
+#[test]
+fn c_heap_traced() {
+    if env::var_os(SHIM_VAR).is_none() {
+        return;
+    }
+
+    // C heap operations are traced, with their usable sizes
+    let (_, stats) = trace_allocs(|| unsafe {
+        let pointer = libc::malloc(100);
+        let size = libc::malloc_usable_size(pointer);
+        assert!(size >= 100);
+        let pointer = libc::realloc(pointer, 1000);
+        libc::free(pointer);
+    });
+    assert_eq!((stats.total_num, stats.reallocs, stats.current), (2, 1, 0));
+    assert!(stats.peak >= 1000);
+
+    let (pointer, stats) = trace_allocs(|| unsafe { libc::calloc(10, 10) });
+    assert_eq!(stats.current, unsafe { libc::malloc_usable_size(pointer) });
+    unsafe { libc::free(pointer) };
+
+    // Rust allocations reach `malloc` through the tracing allocator, but are
+    // counted once
+    let (v, stats) = trace_allocs(|| vec![0_u8; 100]);
+    assert_eq!((stats.total_num, stats.total_size), (1, 100));
+    drop(v);
+}
+
+/// Only does something in the child process started by `preload_shim`.
+#[test]
+fn c_heap_traced_threads() {
+    if env::var_os(SHIM_VAR).is_none() {
+        return;
+    }
+
+    // threads set up and torn down once the hooks are registered call
+    // `malloc` before and after their thread-locals are usable
+    let _ = trace_allocs(|| ());
+    thread::spawn(|| {}).join().unwrap();
+
+    // C heap operations of other threads are traced too
+    let (_, stats) = trace_allocs(|| {
+        thread::scope(|s| {
+            s.spawn(|| unsafe { libc::free(libc::malloc(100)) });
+        })
+    });
+    assert!(stats.total_num >= 1);
+    assert!(stats.total_size >= 100);
+
+    let allocations = thread::spawn(|| {
+        let guard = NoAllocGuard::new(NoAllocMode::Count);
+        unsafe { libc::free(libc::malloc(100)) };
+        guard.allocations()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(allocations, 1);
+}